@@ -1,37 +1,234 @@
-use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CwdAlias {
+    pub path: String,
+    pub alias: String,
+}
 
 #[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct CwdConfiguration {
     pub dironly: bool,
+    /// Directories displayed below the home directory, root or git root.
+    pub max_depth: Option<usize>,
+    /// Shorten parent directories to their first letter, like fish.
+    pub shorten: bool,
+    /// Display the path relative to the git repository root.
+    pub git_root: bool,
+    /// One section per directory.
+    pub split: bool,
+    /// Labels displayed in place of path prefixes, the longest prefix wins.
+    pub aliases: Vec<CwdAlias>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfiguration {
+    pub socket: Option<PathBuf>,
+    pub ttl_ms: u64,
+}
+
+impl Default for DaemonConfiguration {
+    fn default() -> Self {
+        Self {
+            socket: None,
+            ttl_ms: 2000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DurationConfiguration {
+    pub threshold_ms: u64,
+}
+
+impl Default for DurationConfiguration {
+    fn default() -> Self {
+        Self { threshold_ms: 2000 }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExitConfiguration {
+    pub signal_name: bool,
+}
+
+/// File counts displayed by the git segment.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum GitIndicator {
+    Staged,
+    Unstaged,
+    Untracked,
+    Conflicted,
+    Renamed,
+    Deleted,
+}
+
+/// Git hosting service, identified by the host of the `origin` remote.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Forge {
+    Bitbucket,
+    Gitea,
+    Github,
+    Gitlab,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitConfiguration {
+    pub indicators: Vec<GitIndicator>,
+    /// Display the upstream branch when its name differs from the local branch.
+    pub upstream_name: bool,
+    /// Count the commits ahead of and behind this branch too, e.g. `origin/main`.
+    pub base_branch: Option<String>,
+    /// Display the icon of the forge hosting the `origin` remote.
+    pub forge: bool,
+    /// Display the `owner/repo` of the `origin` remote.
+    pub forge_label: bool,
+    /// Forges of self-hosted instances, by host name.
+    #[serde(deserialize_with = "deserialize_forge_hosts")]
+    pub forge_hosts: BTreeMap<String, Forge>,
+    /// Display the age of the HEAD commit.
+    pub commit_age: bool,
+    /// Warn once the working tree has been dirty for this many hours.
+    pub dirty_warning_hours: Option<u64>,
+    pub skip_untracked: bool,
+    /// Stop counting files after this many status entries.
+    pub status_max_entries: Option<usize>,
+    /// Stop counting files after this delay.
+    pub status_timeout_ms: Option<u64>,
+}
+
+// host names are case-insensitive, remote hosts are compared in lowercase
+fn deserialize_forge_hosts<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Forge>, D::Error> {
+    let hosts = BTreeMap::<String, Forge>::deserialize(deserializer)?;
+    Ok(hosts
+        .into_iter()
+        .map(|(host, forge)| (host.to_lowercase(), forge))
+        .collect())
+}
+
+impl Default for GitConfiguration {
+    fn default() -> Self {
+        Self {
+            indicators: GitIndicator::value_variants().to_vec(),
+            upstream_name: false,
+            base_branch: None,
+            forge: false,
+            forge_label: false,
+            forge_hosts: BTreeMap::new(),
+            commit_age: false,
+            dirty_warning_hours: None,
+            skip_untracked: false,
+            status_max_entries: None,
+            status_timeout_ms: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct KubeContextAlias {
     pub context: String,
     pub alias: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct KubeConfiguration {
+    /// Contexts whose name contains one of these get a blinking warning.
     pub critical_contexts: Option<Vec<String>>,
     pub context_aliases: Option<Vec<KubeContextAlias>>,
+    /// Namespaces whose name contains one of these get a blinking warning.
+    pub critical_namespaces: Option<Vec<String>>,
+    /// Display nothing when the namespace is `default`.
+    pub hide_default_namespace: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ContainersConfiguration {
     pub url: String,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct TerraformConfiguration {
     pub critical_workspaces: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TruncationConfiguration {
+    /// Share of the terminal width a prompt line may take.
+    #[serde(deserialize_with = "deserialize_width_fraction")]
+    pub width_fraction: f64,
+}
+
+/// Checks that a share of the terminal width is in (0, 1].
+pub fn check_width_fraction(width_fraction: f64) -> Result<f64, String> {
+    if width_fraction > 0.0 && width_fraction <= 1.0 {
+        Ok(width_fraction)
+    } else {
+        Err(format!(
+            "width fraction must be in (0, 1], got {}",
+            width_fraction
+        ))
+    }
+}
+
+fn deserialize_width_fraction<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    check_width_fraction(f64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+impl Default for TruncationConfiguration {
+    fn default() -> Self {
+        Self {
+            width_fraction: 0.8,
+        }
+    }
+}
+
+/// Glyph drawn between two segments.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SeparatorStyle {
+    // a space, segments are told apart by their background
+    #[default]
+    Plain,
+    Arrow,
+    Thin,
+    Round,
+    Slanted,
+    Ascii,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Configuration {
     pub segments: Vec<String>,
+    pub right_segments: Vec<String>,
     pub theme: String,
+    pub timeout_ms: Option<u64>,
+    pub separator: SeparatorStyle,
     pub cwd: CwdConfiguration,
+    pub daemon: DaemonConfiguration,
+    pub duration: DurationConfiguration,
+    pub exit: ExitConfiguration,
+    pub git: GitConfiguration,
+    pub truncation: TruncationConfiguration,
     pub kube: Option<KubeConfiguration>,
     pub containers: Option<ContainersConfiguration>,
     pub terraform: Option<TerraformConfiguration>,
@@ -41,11 +238,152 @@ impl Default for Configuration {
     fn default() -> Self {
         Self {
             segments: vec!["cwd".into(), "root".into()],
+            right_segments: vec![],
             theme: "default".into(),
+            timeout_ms: None,
+            separator: SeparatorStyle::default(),
             cwd: CwdConfiguration::default(),
+            daemon: DaemonConfiguration::default(),
+            duration: DurationConfiguration::default(),
+            exit: ExitConfiguration::default(),
+            git: GitConfiguration::default(),
+            truncation: TruncationConfiguration::default(),
             kube: None,
             containers: None,
             terraform: None,
         }
     }
 }
+
+/// `$XDG_CONFIG_HOME/opsline`, falling back to `~/.config/opsline`.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_home.join("opsline"))
+}
+
+impl Configuration {
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("config.toml"))
+    }
+
+    /// Reads `path`, or the default configuration file if it exists.
+    pub fn load(path: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error>> {
+        match path {
+            Some(path) => Self::from_file(path),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => Self::from_file(path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let content =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Self::from_toml(&content).map_err(|e| format!("{}:{}", path.display(), e).into())
+    }
+
+    fn from_toml(content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|e| {
+            let (line, column) = e
+                .span()
+                .map(|span| line_column(content, span.start))
+                .unwrap_or((1, 1));
+            format!("{}:{}: {}", line, column, e.message())
+        })
+    }
+}
+
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        let config = Configuration::from_toml(
+            r#"
+segments = ["cwd", "git", "root"]
+theme = "gruvbox"
+separator = "round"
+
+[cwd]
+dironly = true
+aliases = [{ path = "~/work/platform", alias = "PLAT" }]
+
+[kube]
+critical_contexts = ["prod"]
+context_aliases = [{ context = "arn:aws:eks:eu-west-1:1234:cluster/prod", alias = "prod" }]
+critical_namespaces = ["kube-system"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.segments, ["cwd", "git", "root"]);
+        assert_eq!(config.theme, "gruvbox");
+        assert_eq!(config.separator, SeparatorStyle::Round);
+        assert!(config.cwd.dironly);
+        assert_eq!(config.cwd.aliases[0].path, "~/work/platform");
+        let kube = config.kube.unwrap();
+        assert_eq!(kube.critical_contexts.unwrap(), ["prod"]);
+        assert_eq!(kube.context_aliases.unwrap()[0].alias, "prod");
+        assert_eq!(kube.critical_namespaces.unwrap(), ["kube-system"]);
+        assert!(!kube.hide_default_namespace);
+        assert!(config.containers.is_none());
+    }
+
+    #[test]
+    fn test_from_toml_defaults() {
+        let config = Configuration::from_toml("").unwrap();
+        assert_eq!(config.segments, ["cwd", "root"]);
+        assert_eq!(config.theme, "default");
+    }
+
+    #[test]
+    fn test_from_toml_unknown_key() {
+        let err = Configuration::from_toml("theme = \"default\"\n\n[cwd]\ndir_only = true\n")
+            .unwrap_err();
+        assert!(err.starts_with("4:1: unknown field `dir_only`"), "{}", err);
+    }
+
+    #[test]
+    fn test_from_toml_bad_value() {
+        let err = Configuration::from_toml("segments = \"cwd\"\n").unwrap_err();
+        assert!(err.starts_with("1:12: "), "{}", err);
+    }
+
+    #[test]
+    fn test_from_toml_forge_hosts() {
+        let config =
+            Configuration::from_toml("[git]\nforge_hosts = { \"GitLab.Corp.com\" = \"gitlab\" }")
+                .unwrap();
+        assert_eq!(
+            config.git.forge_hosts.get("gitlab.corp.com"),
+            Some(&Forge::Gitlab)
+        );
+    }
+
+    #[test]
+    fn test_from_toml_width_fraction() {
+        let config = Configuration::from_toml("[truncation]\nwidth_fraction = 1.0").unwrap();
+        assert_eq!(config.truncation.width_fraction, 1.0);
+
+        for width_fraction in ["0.0", "1.5", "-0.5"] {
+            let content = format!("[truncation]\nwidth_fraction = {}", width_fraction);
+            let error = Configuration::from_toml(&content).unwrap_err();
+            assert!(error.starts_with("2:"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tracing::{debug, error, info};
+use ureq::Agent;
+
+use crate::configuration::DaemonConfiguration;
+use crate::segments::{ContainersSegment, GitSegment, KubeSegment};
+use crate::utils::ureq_unix::{FakeResolver, UnixConnector};
+
+// the prompt gives up on the daemon and computes inline past this delay
+const REQUEST_TIMEOUT_MS: u64 = 50;
+
+// queries are a few paths and modification times, anything larger is bogus
+const MAX_REQUEST_BYTES: usize = 64 * 1024;
+
+/// Segment data that can be computed and cached by the daemon.
+///
+/// `Query` holds everything the computation depends on (working directory,
+/// file modification times, ...), it is the cache key.
+pub trait Cacheable {
+    const NAME: &'static str;
+    type Query: Serialize + DeserializeOwned;
+    type Data: Serialize + DeserializeOwned;
+
+    fn collect(query: &Self::Query) -> Option<Self::Data>;
+}
+
+pub fn socket_path(config: &DaemonConfiguration) -> PathBuf {
+    if let Some(ref socket) = config.socket {
+        return socket.clone();
+    }
+
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("opsline.sock"),
+        _ => PathBuf::from(format!("/tmp/opsline-{}.sock", unsafe { libc::getuid() })),
+    }
+}
+
+/// Fails unless `path` is a socket owned by the current user that no
+/// other user can connect to.
+///
+/// The socket may live in a shared directory like `/tmp`, where another
+/// user could create it first and serve forged segment texts.
+fn check_socket(path: &Path) -> Result<(), String> {
+    let metadata = path.symlink_metadata().map_err(|e| e.to_string())?;
+    if !metadata.file_type().is_socket() {
+        return Err("not a socket".to_owned());
+    }
+    if metadata.uid() != unsafe { libc::getuid() } {
+        return Err(format!("owned by uid {}", metadata.uid()));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        return Err(format!(
+            "mode {:o} allows other users",
+            metadata.mode() & 0o777
+        ));
+    }
+
+    Ok(())
+}
+
+pub struct DaemonClient {
+    agent: Agent,
+}
+
+impl DaemonClient {
+    /// Returns `None` when no daemon socket exists.
+    pub fn new(config: &DaemonConfiguration) -> Option<Self> {
+        let path = socket_path(config);
+        if !path.exists() {
+            return None;
+        }
+        if let Err(e) = check_socket(&path) {
+            error!("ignoring daemon socket {}: {}", path.display(), e);
+            return None;
+        }
+
+        let config = Agent::config_builder()
+            .timeout_global(Some(Duration::from_millis(REQUEST_TIMEOUT_MS)))
+            .build();
+        let agent = Agent::with_parts(config, UnixConnector::new(path), FakeResolver);
+
+        Some(Self { agent })
+    }
+
+    // Some(data) on cache hit, None on cache miss or daemon error
+    fn get<C: Cacheable>(&self, query: &C::Query) -> Option<Option<C::Data>> {
+        self.agent
+            .post(format!("http://d/segments/{}", C::NAME))
+            .send_json(query)
+            .map_err(|e| debug!(segment = C::NAME, "daemon cache miss: {}", e))
+            .ok()?
+            .body_mut()
+            .read_json::<Option<C::Data>>()
+            .map_err(|_| error!("body deserialization failed"))
+            .ok()
+    }
+}
+
+/// Asks the daemon for cached data, computes it inline if the daemon
+/// is not running or has nothing fresh.
+pub fn fetch<C: Cacheable>(client: Option<&DaemonClient>, query: &C::Query) -> Option<C::Data> {
+    if let Some(data) = client.and_then(|client| client.get::<C>(query)) {
+        return data;
+    }
+
+    C::collect(query)
+}
+
+fn collect_json<C: Cacheable>(query: &str) -> Result<String, serde_json::Error> {
+    let query = serde_json::from_str(query)?;
+    serde_json::to_string(&C::collect(&query))
+}
+
+fn collect(segment: &str, query: &str) -> Option<Result<String, serde_json::Error>> {
+    match segment {
+        ContainersSegment::NAME => Some(collect_json::<ContainersSegment>(query)),
+        GitSegment::NAME => Some(collect_json::<GitSegment>(query)),
+        KubeSegment::NAME => Some(collect_json::<KubeSegment>(query)),
+        _ => None,
+    }
+}
+
+type CacheKey = (String, String);
+
+struct Entry {
+    data: String,
+    updated: Instant,
+}
+
+struct Cache {
+    ttl: Duration,
+    entries: Mutex<HashMap<CacheKey, Entry>>,
+    pending: Mutex<HashSet<CacheKey>>,
+}
+
+impl Cache {
+    fn get(&self, key: &CacheKey) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| entry.updated.elapsed() < self.ttl)
+            .map(|entry| entry.data.clone())
+    }
+
+    fn insert(&self, key: CacheKey, data: String) {
+        let mut entries = self.entries.lock().unwrap();
+        // forget about directories that are not visited anymore
+        entries.retain(|_, entry| entry.updated.elapsed() < self.ttl * 10);
+        entries.insert(
+            key,
+            Entry {
+                data,
+                updated: Instant::now(),
+            },
+        );
+    }
+
+    // compute in the background, the prompt falls back to inline computation meanwhile
+    fn refresh(self: &Arc<Self>, key: CacheKey) {
+        if !self.pending.lock().unwrap().insert(key.clone()) {
+            return;
+        }
+
+        let cache = Arc::clone(self);
+        thread::spawn(move || {
+            let start = Instant::now();
+            match collect(&key.0, &key.1) {
+                Some(Ok(data)) => {
+                    debug!(segment = key.0, duration = ?start.elapsed(), "segment data collected");
+                    cache.insert(key.clone(), data);
+                }
+                Some(Err(e)) => error!(segment = key.0, "invalid query: {}", e),
+                None => error!(segment = key.0, "unknown segment"),
+            }
+            cache.pending.lock().unwrap().remove(&key);
+        });
+    }
+}
+
+struct Request {
+    path: String,
+    body: String,
+}
+
+fn read_request(stream: &UnixStream) -> std::io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let path = line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_owned();
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse().unwrap_or_default();
+        }
+    }
+
+    if content_length > MAX_REQUEST_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("request body of {} bytes is too large", content_length),
+        ));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        path,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+fn write_response(mut stream: &UnixStream, status: &str, body: &str) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn handle(cache: &Arc<Cache>, stream: UnixStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let request = read_request(&stream)?;
+
+    let Some(segment) = request.path.strip_prefix("/segments/") else {
+        return write_response(&stream, "404 Not Found", "");
+    };
+
+    let key = (segment.to_owned(), request.body);
+    match cache.get(&key) {
+        Some(data) => write_response(&stream, "200 OK", &data),
+        None => {
+            cache.refresh(key);
+            write_response(&stream, "404 Not Found", "")
+        }
+    }
+}
+
+fn bind(path: &Path) -> Result<UnixListener, Box<dyn std::error::Error>> {
+    if path.symlink_metadata().is_ok() {
+        // never take over or remove a file planted by another user
+        check_socket(path)?;
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("a daemon is already listening on {}", path.display()).into());
+        }
+        // left behind by a daemon that did not exit cleanly
+        std::fs::remove_file(path)?;
+    }
+
+    // create the socket private rather than restricting it afterwards
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+
+    Ok(listener?)
+}
+
+pub fn run(config: &DaemonConfiguration) -> Result<(), Box<dyn std::error::Error>> {
+    let path = socket_path(config);
+    let listener = bind(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    info!("listening on {}", path.display());
+
+    let cache = Arc::new(Cache {
+        ttl: Duration::from_millis(config.ttl_ms),
+        entries: Mutex::default(),
+        pending: Mutex::default(),
+    });
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    if let Err(e) = handle(&cache, stream) {
+                        error!("failed to handle request: {}", e);
+                    }
+                });
+            }
+            Err(e) => error!("failed to accept connection: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_check_socket() {
+        let dir = std::env::temp_dir().join(format!("opsline-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("opsline.sock");
+
+        let _listener = bind(&path).unwrap();
+        assert_eq!(check_socket(&path), Ok(()));
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666)).unwrap();
+        assert!(check_socket(&path).is_err());
+        // the daemon refuses to take over a socket other users can reach
+        assert!(bind(&path).is_err());
+        assert!(check_socket(&dir).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_request_too_large() {
+        let (client, server) = UnixStream::pair().unwrap();
+        write!(
+            &client,
+            "POST /segments/git HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n"
+        )
+        .unwrap();
+
+        let error = read_request(&server).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::shell::Shell;
+
+// quote an argument so it survives being pasted in a shell script
+fn quote(shell: Shell, arg: &str) -> String {
+    // nushell and powershell give a meaning to commas and @ in bare words
+    let bare = matches!(shell, Shell::Bash | Shell::Zsh | Shell::Fish)
+        && !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=,.:/@%+".contains(c));
+
+    if bare {
+        return arg.to_owned();
+    }
+
+    match shell {
+        Shell::Bash | Shell::Zsh => format!("'{}'", arg.replace('\'', r"'\''")),
+        Shell::Fish => format!("'{}'", arg.replace('\\', r"\\").replace('\'', r"\'")),
+        Shell::Nu => format!("\"{}\"", arg.replace('\\', r"\\").replace('"', "\\\"")),
+        Shell::Pwsh => format!("'{}'", arg.replace('\'', "''")),
+    }
+}
+
+/// Shell integration code, meant to be evaluated from the shell rc file.
+/// `args` are appended to every opsline invocation.
+pub fn script(shell: Shell, args: &[String]) -> String {
+    let args: String = args
+        .iter()
+        .map(|arg| format!(" {}", quote(shell, arg)))
+        .collect();
+
+    match shell {
+        Shell::Bash => format!(
+            r#"_opsline_update_ps1() {{
+    local last_exit_code=$?
+    local jobs duration
+    jobs=$(jobs -p | wc -l)
+    if [ -n "$_opsline_start" ]; then
+        duration=$(( (${{EPOCHREALTIME/[.,]/}} - _opsline_start) / 1000 ))
+        unset _opsline_start
+    fi
+    PS1="$(opsline --shell bash --last-exit-code "$last_exit_code" --jobs "$jobs" ${{duration:+--duration-ms "$duration"}}{args})"
+}}
+
+if [ "$TERM" != "linux" ] && command -v opsline >/dev/null 2>&1; then
+    if [[ "${{PROMPT_COMMAND[*]}}" != *_opsline_update_ps1* ]]; then
+        PROMPT_COMMAND="_opsline_update_ps1${{PROMPT_COMMAND:+; $PROMPT_COMMAND}}"
+        # PS0 is expanded right before a command runs, record its start time in µs
+        if [ -n "$EPOCHREALTIME" ]; then
+            PS0='${{_opsline_ps0[_opsline_start=${{EPOCHREALTIME/[.,]/}}, 0]}}'"$PS0"
+        fi
+    fi
+fi
+"#
+        ),
+        Shell::Zsh => format!(
+            r#"zmodload zsh/datetime 2>/dev/null
+
+_opsline_preexec() {{
+    _opsline_start=$EPOCHREALTIME
+}}
+
+_opsline_update_ps1() {{
+    local last_exit_code=$?
+    local -a duration
+    if [[ -n "$_opsline_start" ]]; then
+        duration=(--command-start "$_opsline_start")
+        unset _opsline_start
+    fi
+    PS1="$(opsline --shell zsh --last-exit-code "$last_exit_code" --jobs "${{(%):-%j}}" $duration{args})"
+    RPROMPT="$(opsline --shell zsh --right --last-exit-code "$last_exit_code" --jobs "${{(%):-%j}}" $duration{args})"
+}}
+
+if [ "$TERM" != "linux" ] && command -v opsline >/dev/null 2>&1; then
+    # the prompt escapes $, ` and \ in displayed texts, which prompt_subst removes
+    setopt prompt_subst
+    if (( ! ${{precmd_functions[(I)_opsline_update_ps1]}} )); then
+        precmd_functions=(_opsline_update_ps1 $precmd_functions)
+        preexec_functions+=(_opsline_preexec)
+    fi
+fi
+"#
+        ),
+        Shell::Fish => format!(
+            r#"if test "$TERM" != linux; and command -q opsline
+    function fish_prompt
+        set -l last_exit_code $status
+        set -l duration
+        # CMD_DURATION is not reset when an empty command line is submitted
+        if test "$status_generation" != "$_opsline_status_generation"
+            set duration --duration-ms $CMD_DURATION
+        end
+        set -g _opsline_status_generation $status_generation
+        # fish_right_prompt runs after fish_prompt, and sees the same status
+        set -g _opsline_duration $duration
+        opsline --shell fish --last-exit-code $last_exit_code --jobs (count (jobs -p)) $duration{args}
+    end
+
+    function fish_right_prompt
+        set -l last_exit_code $status
+        opsline --shell fish --right --last-exit-code $last_exit_code --jobs (count (jobs -p)) $_opsline_duration{args}
+    end
+end
+"#
+        ),
+        Shell::Nu => format!(
+            r#"if ($env.TERM? != "linux") and (which opsline | is-not-empty) {{
+    $env.PROMPT_COMMAND = {{||
+        opsline --shell nu --last-exit-code $env.LAST_EXIT_CODE --duration-ms $env.CMD_DURATION_MS{args}
+    }}
+    $env.PROMPT_COMMAND_RIGHT = {{||
+        opsline --shell nu --right --last-exit-code $env.LAST_EXIT_CODE --duration-ms $env.CMD_DURATION_MS{args}
+    }}
+    $env.PROMPT_INDICATOR = ""
+}}
+"#
+        ),
+        Shell::Pwsh => format!(
+            r#"if ($env:TERM -ne "linux" -and (Get-Command opsline -CommandType Application -ErrorAction SilentlyContinue)) {{
+    function global:prompt {{
+        $success = $global:?
+        $lastExitCode = $global:LASTEXITCODE
+        $exitCode = if ($success) {{ 0 }} elseif ($lastExitCode) {{ $lastExitCode }} else {{ 1 }}
+        $jobs = @(Get-Job -State Running).Count
+        $duration = @()
+        $last = Get-History -Count 1
+        if ($last -and $last.Id -ne $global:_opsline_history_id) {{
+            $global:_opsline_history_id = $last.Id
+            $duration = '--duration-ms', [long]($last.EndExecutionTime - $last.StartExecutionTime).TotalMilliseconds
+        }}
+        $prompt = opsline --shell pwsh --last-exit-code $exitCode --jobs $jobs @duration{args}
+        $global:LASTEXITCODE = $lastExitCode
+        $prompt -join "`n"
+    }}
+}}
+"#
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote(Shell::Bash, "cwd,git,root"), "cwd,git,root");
+        assert_eq!(quote(Shell::Bash, "k8s-prod:prod"), "k8s-prod:prod");
+        assert_eq!(quote(Shell::Bash, ""), "''");
+        assert_eq!(quote(Shell::Bash, "a b"), "'a b'");
+        assert_eq!(quote(Shell::Bash, "it's"), r"'it'\''s'");
+        assert_eq!(quote(Shell::Fish, r"it's a\b"), r"'it\'s a\\b'");
+        assert_eq!(quote(Shell::Nu, r#"a"b\"#), r#""a\"b\\""#);
+        assert_eq!(quote(Shell::Pwsh, "cwd,root"), "'cwd,root'");
+        assert_eq!(quote(Shell::Pwsh, "it's"), "'it''s'");
+    }
+
+    #[test]
+    fn test_script_forwards_args() {
+        let script = script(Shell::Zsh, &["--theme".into(), "gruvbox".into()]);
+        assert!(script.contains(r#"--jobs "${(%):-%j}" $duration --theme gruvbox)""#));
+        assert!(script.contains("setopt prompt_subst"));
+    }
+}
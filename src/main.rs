@@ -1,10 +1,17 @@
-use clap::Parser;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::{Parser, Subcommand};
+use tracing::debug;
 use tracing_subscriber::{EnvFilter, fmt};
 
+use configuration::{Configuration, GitIndicator, SeparatorStyle};
+use daemon::DaemonClient;
 use powerline::Powerline;
 use segments::{
-    ContainersSegment, CwdSegment, DevwrapSegment, GitSegment, KubeSegment, ReadonlySegment,
-    RootSegment, SshSegment, TerraformSegment,
+    ContainersSegment, CwdSegment, DevwrapSegment, DurationSegment, ExitSegment, GitSegment,
+    KubeSegment, ReadonlySegment, RootSegment, Segment, SshSegment, TerraformSegment,
 };
 use shell::Shell;
 use theme::Theme;
@@ -12,6 +19,8 @@ use theme::Theme;
 use crate::segments::aws::AwsSegment;
 
 mod configuration;
+mod daemon;
+mod init;
 mod powerline;
 mod segments;
 mod shell;
@@ -20,103 +29,388 @@ mod utils;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(long, value_parser = ["bash", "zsh"])]
-    shell: String,
-    #[arg(long, value_parser = ["default", "gruvbox"], default_value = "default")]
-    theme: String,
-    #[arg(long, default_value = "cwd,root")]
-    segments: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(long, value_parser = ["bash", "zsh", "fish", "nu", "pwsh"], required = true)]
+    shell: Option<String>,
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// default, gruvbox, or the name or path of a theme file
+    #[arg(long)]
+    theme: Option<String>,
+    #[arg(long)]
+    segments: Option<String>,
+    /// Segments of the right prompt
+    #[arg(long)]
+    right_segments: Option<String>,
+    /// Segments taking longer than this are left out of the prompt
+    #[arg(long)]
+    timeout_ms: Option<u64>,
+    /// Glyph drawn between segments
+    #[arg(long, value_enum)]
+    separator: Option<SeparatorStyle>,
     #[arg(long, default_value_t = false)]
     cwd_dironly: bool,
     #[arg(long)]
+    cwd_max_depth: Option<usize>,
+    #[arg(long, default_value_t = false)]
+    cwd_shorten: bool,
+    #[arg(long, default_value_t = false)]
+    cwd_git_root: bool,
+    #[arg(long, default_value_t = false)]
+    cwd_split: bool,
+    #[arg(long)]
+    cwd_aliases: Option<String>,
+    #[arg(long)]
+    duration_threshold_ms: Option<u64>,
+    #[arg(long, default_value_t = false)]
+    exit_signal_name: bool,
+    /// File counts displayed by the git segment
+    #[arg(long, value_enum, value_delimiter = ',')]
+    git_indicators: Option<Vec<GitIndicator>>,
+    #[arg(long, default_value_t = false)]
+    git_upstream_name: bool,
+    #[arg(long)]
+    git_base_branch: Option<String>,
+    #[arg(long, default_value_t = false)]
+    git_forge: bool,
+    #[arg(long, default_value_t = false)]
+    git_forge_label: bool,
+    #[arg(long, default_value_t = false)]
+    git_commit_age: bool,
+    #[arg(long)]
+    git_dirty_warning_hours: Option<u64>,
+    #[arg(long, default_value_t = false)]
+    git_skip_untracked: bool,
+    #[arg(long)]
+    git_status_max_entries: Option<usize>,
+    #[arg(long)]
+    git_status_timeout_ms: Option<u64>,
+    /// Share of the terminal width a prompt line may take, between 0 and 1
+    #[arg(long, value_parser = parse_width_fraction)]
+    truncation_width_fraction: Option<f64>,
+    #[arg(long)]
     kube_critical_contexts: Option<String>,
     #[arg(long)]
     kube_context_aliases: Option<String>,
     #[arg(long)]
+    kube_critical_namespaces: Option<String>,
+    #[arg(long, default_value_t = false)]
+    kube_hide_default_namespace: bool,
+    #[arg(long)]
     containers_url: Option<String>,
     #[arg(long)]
     terraform_critical_workspaces: Option<String>,
+    /// Exit status of the previous command, set by the shell integration
+    #[arg(long, allow_hyphen_values = true)]
+    last_exit_code: Option<i32>,
+    /// Number of background jobs, set by the shell integration
+    #[arg(long)]
+    jobs: Option<usize>,
+    /// Duration of the previous command, set by the shell integration
+    #[arg(long, conflicts_with = "command_start")]
+    duration_ms: Option<u64>,
+    /// Start time of the previous command in seconds since the epoch, set by the shell integration
+    #[arg(long, value_parser = parse_timestamp)]
+    command_start: Option<Duration>,
+    /// Print the right prompt, set by the shell integration
+    #[arg(long, default_value_t = false)]
+    right: bool,
 }
 
-fn main() {
-    fmt()
-        .with_env_filter(
-            EnvFilter::try_from_env("OPSLINE_LOG").unwrap_or_else(|_| EnvFilter::new("none")),
-        )
-        .init();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the shell integration code, e.g. `eval "$(opsline init bash)"`
+    Init {
+        #[arg(value_parser = ["bash", "zsh", "fish", "nu", "pwsh"])]
+        shell: String,
+        /// Arguments passed to opsline on every prompt
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Compute and cache segment data in the background
+    Daemon {
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+}
 
-    let args = Args::parse();
+// seconds since the epoch with an optional fractional part, as found in
+// $EPOCHREALTIME which uses the locale decimal separator
+fn parse_timestamp(s: &str) -> Result<Duration, String> {
+    s.replace(',', ".")
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("invalid timestamp: {}", s))
+}
 
-    let shell = Shell::try_from(args.shell.as_str()).expect("failed to set shell");
-    let theme = Theme::try_from(args.theme.as_str()).expect("failed to set theme");
-
-    let segments: Vec<String> = args
-        .segments
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .collect();
-
-    let config = configuration::Configuration {
-        segments,
-        theme: args.theme.to_string(),
-        cwd: configuration::CwdConfiguration {
-            dironly: args.cwd_dironly,
-        },
-        kube: if args.kube_critical_contexts.is_some() || args.kube_context_aliases.is_some() {
-            let critical_contexts = args
-                .kube_critical_contexts
-                .map(|s| s.split(',').map(|s| s.trim().to_string()).collect());
-
-            let context_aliases = args.kube_context_aliases.map(|s| {
-                s.split(',')
+fn parse_width_fraction(s: &str) -> Result<f64, String> {
+    let width_fraction = s
+        .parse()
+        .map_err(|_| format!("invalid width fraction: {}", s))?;
+    configuration::check_width_fraction(width_fraction)
+}
+
+fn split_list(s: &str) -> Vec<String> {
+    s.split(',').map(|s| s.trim().to_string()).collect()
+}
+
+/// Loads the configuration file, then overrides it with command-line flags.
+fn load_configuration(args: Args) -> Result<Configuration, Box<dyn std::error::Error>> {
+    let mut config = Configuration::load(args.config)?;
+
+    if let Some(segments) = args.segments {
+        config.segments = split_list(&segments);
+    }
+    if let Some(right_segments) = args.right_segments {
+        config.right_segments = split_list(&right_segments);
+    }
+    if let Some(theme) = args.theme {
+        config.theme = theme;
+    }
+    if let Some(timeout_ms) = args.timeout_ms {
+        config.timeout_ms = Some(timeout_ms);
+    }
+    if let Some(separator) = args.separator {
+        config.separator = separator;
+    }
+    if args.cwd_dironly {
+        config.cwd.dironly = true;
+    }
+    if let Some(max_depth) = args.cwd_max_depth {
+        config.cwd.max_depth = Some(max_depth);
+    }
+    if args.cwd_shorten {
+        config.cwd.shorten = true;
+    }
+    if args.cwd_git_root {
+        config.cwd.git_root = true;
+    }
+    if args.cwd_split {
+        config.cwd.split = true;
+    }
+    if let Some(aliases) = args.cwd_aliases {
+        config.cwd.aliases = aliases
+            .split(',')
+            .map(|pair| {
+                if let Some((lhs, rhs)) = pair.rsplit_once(':') {
+                    Ok(configuration::CwdAlias {
+                        path: lhs.trim().to_string(),
+                        alias: rhs.trim().to_string(),
+                    })
+                } else {
+                    Err(format!("invalid directory alias format: {}", pair))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+    }
+    if let Some(threshold_ms) = args.duration_threshold_ms {
+        config.duration.threshold_ms = threshold_ms;
+    }
+    if args.exit_signal_name {
+        config.exit.signal_name = true;
+    }
+    if let Some(indicators) = args.git_indicators {
+        config.git.indicators = indicators;
+    }
+    if args.git_upstream_name {
+        config.git.upstream_name = true;
+    }
+    if let Some(base_branch) = args.git_base_branch {
+        config.git.base_branch = Some(base_branch);
+    }
+    if args.git_forge {
+        config.git.forge = true;
+    }
+    if args.git_forge_label {
+        config.git.forge_label = true;
+    }
+    if args.git_commit_age {
+        config.git.commit_age = true;
+    }
+    if let Some(hours) = args.git_dirty_warning_hours {
+        config.git.dirty_warning_hours = Some(hours);
+    }
+    if args.git_skip_untracked {
+        config.git.skip_untracked = true;
+    }
+    if let Some(max_entries) = args.git_status_max_entries {
+        config.git.status_max_entries = Some(max_entries);
+    }
+    if let Some(timeout_ms) = args.git_status_timeout_ms {
+        config.git.status_timeout_ms = Some(timeout_ms);
+    }
+    if let Some(width_fraction) = args.truncation_width_fraction {
+        config.truncation.width_fraction = width_fraction;
+    }
+
+    if args.kube_critical_contexts.is_some()
+        || args.kube_context_aliases.is_some()
+        || args.kube_critical_namespaces.is_some()
+        || args.kube_hide_default_namespace
+    {
+        let kube = config.kube.get_or_insert_default();
+
+        if let Some(critical_contexts) = args.kube_critical_contexts {
+            kube.critical_contexts = Some(split_list(&critical_contexts));
+        }
+
+        if let Some(context_aliases) = args.kube_context_aliases {
+            kube.context_aliases = Some(
+                context_aliases
+                    .split(',')
                     .map(|pair| {
                         if let Some((lhs, rhs)) = pair.rsplit_once(':') {
-                            configuration::KubeContextAlias {
+                            Ok(configuration::KubeContextAlias {
                                 context: lhs.trim().to_string(),
                                 alias: rhs.trim().to_string(),
-                            }
+                            })
                         } else {
-                            panic!("Invalid context alias format: {}", pair);
+                            Err(format!("invalid context alias format: {}", pair))
                         }
                     })
-                    .collect()
-            });
+                    .collect::<Result<_, _>>()?,
+            );
+        }
 
-            Some(configuration::KubeConfiguration {
-                critical_contexts,
-                context_aliases,
-            })
-        } else {
-            None
-        },
-        containers: args
-            .containers_url
-            .map(|url| configuration::ContainersConfiguration { url }),
-        terraform: args.terraform_critical_workspaces.map(|s| {
-            configuration::TerraformConfiguration {
-                critical_workspaces: s.split(',').map(|s| s.trim().to_string()).collect(),
-            }
-        }),
+        if let Some(critical_namespaces) = args.kube_critical_namespaces {
+            kube.critical_namespaces = Some(split_list(&critical_namespaces));
+        }
+
+        if args.kube_hide_default_namespace {
+            kube.hide_default_namespace = true;
+        }
+    }
+
+    if let Some(url) = args.containers_url {
+        config.containers = Some(configuration::ContainersConfiguration { url });
+    }
+    if let Some(critical_workspaces) = args.terraform_critical_workspaces {
+        config.terraform = Some(configuration::TerraformConfiguration {
+            critical_workspaces: split_list(&critical_workspaces),
+        });
+    }
+
+    Ok(config)
+}
+
+fn new_segment(
+    name: &str,
+    config: &'static Configuration,
+    daemon: Option<&'static DaemonClient>,
+    last_exit_code: Option<i32>,
+    duration: Option<Duration>,
+) -> Option<Arc<dyn Segment>> {
+    let segment: Arc<dyn Segment> = match name {
+        "aws" => Arc::new(AwsSegment::new()),
+        "containers" => Arc::new(ContainersSegment::new(config.containers.as_ref(), daemon)),
+        "cwd" => Arc::new(CwdSegment::new(&config.cwd)),
+        "devwrap" => Arc::new(DevwrapSegment::new()),
+        "duration" => Arc::new(DurationSegment::new(&config.duration, duration)),
+        "exit" => Arc::new(ExitSegment::new(&config.exit, last_exit_code)),
+        "git" => Arc::new(GitSegment::new(&config.git, daemon)),
+        "kube" => Arc::new(KubeSegment::new(config.kube.as_ref(), daemon)),
+        "readonly" => Arc::new(ReadonlySegment::new()),
+        "root" => Arc::new(RootSegment::new()),
+        "ssh" => Arc::new(SshSegment::new()),
+        "terraform" => Arc::new(TerraformSegment::new(config.terraform.as_ref())),
+        _ => return None,
     };
 
-    let mut powerline: Powerline = Powerline::new(shell, theme);
+    Some(segment)
+}
+
+fn main() {
+    fmt()
+        .with_env_filter(
+            EnvFilter::try_from_env("OPSLINE_LOG").unwrap_or_else(|_| EnvFilter::new("none")),
+        )
+        .init();
+
+    let args = Args::parse();
+    debug!(
+        "shell integration: last exit code {:?}, {:?} jobs",
+        args.last_exit_code, args.jobs
+    );
 
-    for segment in config.segments {
-        match segment.as_str() {
-            "aws" => powerline.add_segment(AwsSegment::new()),
-            "containers" => {
-                powerline.add_segment(ContainersSegment::new(config.containers.as_ref()))
+    match args.command {
+        Some(Command::Init { shell, args }) => {
+            let shell = Shell::try_from(shell.as_str()).expect("failed to set shell");
+            print!("{}", init::script(shell, &args));
+            return;
+        }
+        Some(Command::Daemon { config }) => {
+            if let Err(e) =
+                Configuration::load(config).and_then(|config| daemon::run(&config.daemon))
+            {
+                eprintln!("opsline: {}", e);
+                std::process::exit(1);
             }
-            "cwd" => powerline.add_segment(CwdSegment::new(&config.cwd)),
-            "devwrap" => powerline.add_segment(DevwrapSegment::new()),
-            "git" => powerline.add_segment(GitSegment::new()),
-            "kube" => powerline.add_segment(KubeSegment::new(config.kube.as_ref())),
-            "readonly" => powerline.add_segment(ReadonlySegment::new()),
-            "root" => powerline.add_segment(RootSegment::new()),
-            "ssh" => powerline.add_segment(SshSegment::new()),
-            "terraform" => powerline.add_segment(TerraformSegment::new(config.terraform.as_ref())),
-            s => panic!("unknown segment name: {}", s),
+            return;
+        }
+        None => {}
+    }
+
+    let shell =
+        Shell::try_from(args.shell.as_deref().unwrap_or_default()).expect("failed to set shell");
+    let last_exit_code = args.last_exit_code;
+    let right = args.right;
+    let duration = args.duration_ms.map(Duration::from_millis).or_else(|| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+        now.checked_sub(args.command_start?)
+    });
+
+    let config = load_configuration(args).unwrap_or_else(|e| {
+        eprintln!("opsline: {}", e);
+        std::process::exit(1);
+    });
+    // segments run on detached threads that may outlive the prompt
+    let config: &'static Configuration = Box::leak(Box::new(config));
+
+    let theme = Theme::load(config.theme.as_str()).unwrap_or_else(|e| {
+        eprintln!("opsline: {}", e);
+        std::process::exit(1);
+    });
+
+    let daemon: Option<&'static DaemonClient> =
+        DaemonClient::new(&config.daemon).map(|client| &*Box::leak(Box::new(client)));
+
+    let mut powerline = Powerline::new(shell, theme);
+    powerline.set_timeout(config.timeout_ms.map(Duration::from_millis));
+    powerline.set_separator(config.separator);
+    powerline.set_max_width(
+        powerline::terminal_width()
+            .map(|columns| (columns as f64 * config.truncation.width_fraction) as usize),
+    );
+
+    let segment = |name: &String| {
+        new_segment(name, config, daemon, last_exit_code, duration).unwrap_or_else(|| {
+            eprintln!("opsline: unknown segment name: {}", name);
+            std::process::exit(1);
+        })
+    };
+
+    if right {
+        for name in &config.right_segments {
+            powerline.add_right_segment(segment(name));
+        }
+        powerline.right_prompt();
+        return;
+    }
+
+    for name in &config.segments {
+        match name.as_str() {
+            "newline" => powerline.add_newline(),
+            _ => powerline.add_segment(segment(name)),
+        }
+    }
+    // rendered along with the left prompt by shells without a right prompt
+    if matches!(shell, Shell::Bash | Shell::Pwsh) {
+        for name in &config.right_segments {
+            powerline.add_right_segment(segment(name));
         }
     }
 
@@ -1,73 +1,625 @@
+use crate::configuration::SeparatorStyle;
 use crate::segments::{Segment, SegmentSection};
 use crate::shell::Shell;
-use crate::theme::{Blink, Reset, Theme};
-use std::time::Instant;
+use crate::theme::{BackgroundColor, Blink, ForegroundColor, Reset, Theme};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
 use tracing::debug;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-pub struct Powerline<'a> {
+pub struct Powerline {
     shell: Shell,
-    theme: Theme,
-    segments: Vec<Box<dyn Segment + 'a>>,
+    theme: Arc<Theme>,
+    timeout: Option<Duration>,
+    separator: SeparatorStyle,
+    segments: Vec<Arc<dyn Segment>>,
+    right_segments: Vec<Arc<dyn Segment>>,
+    // number of segments before each line break
+    line_breaks: Vec<usize>,
+    max_width: Option<usize>,
 }
 
-impl<'a> Powerline<'a> {
+impl Powerline {
     pub fn new(shell: Shell, theme: Theme) -> Self {
         Self {
             shell,
-            theme,
+            theme: Arc::new(theme),
+            timeout: None,
+            separator: SeparatorStyle::default(),
             segments: vec![],
+            right_segments: vec![],
+            line_breaks: vec![],
+            max_width: None,
         }
     }
 
-    pub fn add_segment(&mut self, segment: impl Segment + 'a) {
-        self.segments.push(Box::new(segment));
+    /// Segments that did not complete within `timeout` are left out of the prompt.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
     }
 
-    pub fn prompt(&self) {
-        let sections: Vec<_> = self
-            .segments
-            .iter()
-            .filter_map(|s| {
+    pub fn set_separator(&mut self, separator: SeparatorStyle) {
+        self.separator = separator;
+    }
+
+    pub fn add_segment(&mut self, segment: Arc<dyn Segment>) {
+        self.segments.push(segment);
+    }
+
+    /// Lines wider than `max_width` columns are shortened to fit.
+    pub fn set_max_width(&mut self, max_width: Option<usize>) {
+        self.max_width = max_width;
+    }
+
+    /// Following segments are displayed on a new line.
+    pub fn add_newline(&mut self) {
+        self.line_breaks.push(self.segments.len());
+    }
+
+    pub fn add_right_segment(&mut self, segment: Arc<dyn Segment>) {
+        self.right_segments.push(segment);
+    }
+
+    // run every segment in its own thread. Threads are detached so that a
+    // segment missing the deadline does not hold the prompt back.
+    fn render_segments(&self, segments: &[Arc<dyn Segment>]) -> Vec<Option<Vec<SegmentSection>>> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let (tx, rx) = mpsc::channel();
+
+        for (i, segment) in segments.iter().enumerate() {
+            let segment = Arc::clone(segment);
+            let theme = Arc::clone(&self.theme);
+            let shell = self.shell;
+            let tx = tx.clone();
+
+            thread::spawn(move || {
                 let start = Instant::now();
-                let mut sections = s.output(self.shell, &self.theme);
+                let sections = segment.output(shell, &theme);
                 let duration = start.elapsed();
-                debug!(segment = s.name(), duration = ?duration, "segment completed");
+                debug!(segment = segment.name(), duration = ?duration, "segment completed");
 
-                if let Some(ref mut sections) = sections {
-                    sections.push(SegmentSection::Seperator);
+                // the receiver is gone if the deadline has passed
+                let _ = tx.send((i, sections));
+            });
+        }
+        drop(tx);
+
+        let mut outputs: Vec<_> = segments.iter().map(|_| None).collect();
+        for _ in 0..segments.len() {
+            let received = match deadline {
+                Some(deadline) => {
+                    rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
                 }
-                sections
+                None => rx.recv().map_err(mpsc::RecvTimeoutError::from),
+            };
+
+            match received {
+                Ok((i, sections)) => outputs[i] = Some(sections),
+                Err(_) => break,
+            }
+        }
+
+        segments
+            .iter()
+            .zip(outputs)
+            .map(|(segment, output)| {
+                output.unwrap_or_else(|| {
+                    debug!(segment = segment.name(), timeout = ?self.timeout, "segment timed out");
+                    None
+                })
             })
-            .flatten()
-            .collect();
+            .collect()
+    }
+
+    fn separator_glyph(&self) -> &'static str {
+        match self.separator {
+            SeparatorStyle::Plain => " ",
+            SeparatorStyle::Arrow => "\u{e0b0}",
+            SeparatorStyle::Thin => "\u{e0b1}",
+            SeparatorStyle::Round => "\u{e0b4}",
+            SeparatorStyle::Slanted => "\u{e0bc}",
+            SeparatorStyle::Ascii => ">",
+        }
+    }
+
+    fn right_separator_glyph(&self) -> &'static str {
+        match self.separator {
+            SeparatorStyle::Plain => " ",
+            SeparatorStyle::Arrow => "\u{e0b2}",
+            SeparatorStyle::Thin => "\u{e0b3}",
+            SeparatorStyle::Round => "\u{e0b6}",
+            SeparatorStyle::Slanted => "\u{e0ba}",
+            SeparatorStyle::Ascii => "<",
+        }
+    }
+
+    fn render_section(&self, section: &SegmentSection) -> String {
+        let mut rendered = String::new();
+        if let SegmentSection::Section {
+            text,
+            bg,
+            fg,
+            blinking,
+        } = section
+        {
+            if *blinking {
+                rendered += &Blink.fmt(self.shell).to_string();
+            }
+            rendered += &format!(
+                r"{}{} {}{}",
+                bg.fmt(self.shell),
+                fg.fmt(self.shell),
+                text,
+                Reset.fmt(self.shell)
+            );
+        }
+        rendered
+    }
+
+    // the separator is drawn with the background of the segment it closes as
+    // foreground, over the background of the segment that follows
+    fn render(&self, sections: &[SegmentSection]) -> String {
+        let mut prompt = String::new();
+        let mut previous_bg = BackgroundColor::colorless();
 
         for (i, section) in sections.iter().enumerate() {
             match section {
-                SegmentSection::Section {
-                    text,
-                    bg,
-                    fg,
-                    blinking,
-                } => {
-                    if *blinking {
-                        print!("{}", Blink.fmt(self.shell));
-                    }
-                    print!(
-                        r"{}{} {}{}",
-                        bg.fmt(self.shell),
-                        fg.fmt(self.shell),
-                        text,
-                        Reset.fmt(self.shell)
+                SegmentSection::Section { bg, .. } => {
+                    prompt += &self.render_section(section);
+                    previous_bg = *bg;
+                }
+                // a space without background, which also ends the line
+                SegmentSection::Seperator if self.separator == SeparatorStyle::Plain => {
+                    prompt += &format!("{}{}", self.separator_glyph(), Reset.fmt(self.shell));
+                }
+                SegmentSection::Seperator => {
+                    let next_bg = sections[i + 1..].iter().find_map(|section| match section {
+                        SegmentSection::Section { bg, .. } => Some(*bg),
+                        SegmentSection::Seperator => None,
+                    });
+
+                    prompt += &format!(
+                        "{} {}{}",
+                        previous_bg.fmt(self.shell),
+                        Reset.fmt(self.shell),
+                        ForegroundColor::from(previous_bg).fmt(self.shell),
                     );
+                    match next_bg {
+                        Some(next_bg) => {
+                            prompt += &format!(
+                                "{}{}{}",
+                                next_bg.fmt(self.shell),
+                                self.separator_glyph(),
+                                Reset.fmt(self.shell)
+                            );
+                        }
+                        None => {
+                            prompt +=
+                                &format!("{}{} ", self.separator_glyph(), Reset.fmt(self.shell));
+                        }
+                    }
+                }
+            }
+        }
+
+        prompt
+    }
+
+    // mirrored separators: each one comes before its segment, drawn with
+    // the segment background as foreground
+    fn render_right(&self, sections: &[SegmentSection]) -> String {
+        let mut prompt = String::new();
+        let mut previous_bg = None;
+
+        for (i, section) in sections.iter().enumerate() {
+            match section {
+                SegmentSection::Section { bg, .. } => {
+                    prompt += &self.render_section(section);
+                    previous_bg = Some(*bg);
                 }
                 SegmentSection::Seperator => {
-                    if i == sections.len() - 1 {
-                        print!(r" {}", Reset.fmt(self.shell),);
-                    } else {
-                        print!(r" {}", Reset.fmt(self.shell),);
+                    let Some(next_bg) =
+                        sections[i + 1..].iter().find_map(|section| match section {
+                            SegmentSection::Section { bg, .. } => Some(*bg),
+                            SegmentSection::Seperator => None,
+                        })
+                    else {
+                        continue;
+                    };
+
+                    if let Some(previous_bg) = previous_bg {
+                        prompt += &format!("{} ", previous_bg.fmt(self.shell));
                     }
+                    if self.separator == SeparatorStyle::Plain {
+                        prompt +=
+                            &format!("{}{}", Reset.fmt(self.shell), self.right_separator_glyph());
+                        continue;
+                    }
+                    prompt += &format!(
+                        "{}{}{}",
+                        ForegroundColor::from(next_bg).fmt(self.shell),
+                        self.right_separator_glyph(),
+                        Reset.fmt(self.shell)
+                    );
+                }
+            }
+        }
+
+        if let Some(previous_bg) = previous_bg {
+            prompt += &format!("{} {}", previous_bg.fmt(self.shell), Reset.fmt(self.shell));
+        }
+
+        prompt
+    }
+
+    // columns taken by the displayed text, shell escapes excluded
+    fn text_width(&self, text: &str) -> usize {
+        self.shell.unescape(text).width()
+    }
+
+    // columns taken by the rendered sections, a separator is a padding space and a glyph
+    fn width(&self, sections: &[SegmentSection]) -> usize {
+        sections
+            .iter()
+            .map(|section| match section {
+                SegmentSection::Section { text, .. } => 1 + self.text_width(text),
+                SegmentSection::Seperator => 2,
+            })
+            .sum()
+    }
+
+    // drop the segments with the lowest priority until the line fits in
+    // max_width. The segment with the highest priority is always kept, its
+    // long texts are shortened when it does not fit on its own.
+    fn fit(
+        &self,
+        outputs: &mut [Option<Vec<SegmentSection>>],
+        segments: &[Arc<dyn Segment>],
+        separator_first: bool,
+    ) {
+        let Some(max_width) = self.max_width else {
+            return;
+        };
+        // a left line ends with a space after its last separator, the plain
+        // separator is that space
+        let plain = !separator_first && self.separator == SeparatorStyle::Plain;
+        let separator_width = if plain { 1 } else { 2 };
+        let line_width = |outputs: &[Option<Vec<SegmentSection>>]| -> usize {
+            let mut sections = outputs.iter().flatten().peekable();
+            let trailing_space =
+                usize::from(!separator_first && !plain && sections.peek().is_some());
+            sections
+                .map(|sections| self.width(sections) + separator_width)
+                .sum::<usize>()
+                + trailing_space
+        };
+
+        let mut by_priority: Vec<_> = (0..segments.len()).collect();
+        by_priority.sort_by_key(|&i| segments[i].priority());
+
+        for &i in by_priority.iter().take(segments.len().saturating_sub(1)) {
+            if line_width(outputs) <= max_width {
+                return;
+            }
+            if outputs[i].take().is_some() {
+                debug!(
+                    segment = segments[i].name(),
+                    "segment dropped to fit the terminal"
+                );
+            }
+        }
+
+        let mut width = line_width(outputs);
+        for section in outputs.iter_mut().flatten().flatten() {
+            if width <= max_width {
+                return;
+            }
+            // escapes are removed before shortening, so that none is cut in half
+            if let SegmentSection::Section { text, .. } = section {
+                let displayed = self.shell.unescape(text).into_owned();
+                let text_width = displayed.width();
+                if text_width > MIN_TRUNCATED_WIDTH {
+                    let truncated_width = text_width
+                        .saturating_sub(width - max_width)
+                        .max(MIN_TRUNCATED_WIDTH);
+                    let truncated = truncate(&displayed, truncated_width);
+                    width = width - text_width + truncated.width();
+                    *text = self.shell.escape(&truncated).into_owned().into();
                 }
             }
         }
     }
+
+    // bash and powershell have no right prompt: it is printed at the end of
+    // the line before the left prompt, and the cursor moved back
+    fn inline_right(&self, sections: &[SegmentSection]) -> String {
+        let right = format!(
+            "\x1b7\x1b[999C\x1b[{}D{}\x1b8",
+            self.width(sections),
+            self.render_right(sections)
+        );
+
+        match self.shell {
+            // zero width as a whole, the nested \[ \] would end it early
+            Shell::Bash => format!(r"\[{}\]", right.replace(r"\[", "").replace(r"\]", "")),
+            _ => right,
+        }
+    }
+
+    fn render_prompt(&self) -> String {
+        let inline_right = matches!(self.shell, Shell::Bash | Shell::Pwsh);
+        let mut segments = self.segments.clone();
+        if inline_right {
+            segments.extend(self.right_segments.iter().cloned());
+        }
+
+        let mut outputs = self.render_segments(&segments);
+        let mut right_outputs = outputs.split_off(self.segments.len());
+        self.fit(&mut right_outputs, &self.right_segments, true);
+        let right_sections = join(right_outputs, true);
+
+        let mut prompt = String::new();
+        if inline_right && !right_sections.is_empty() {
+            prompt += &self.inline_right(&right_sections);
+        }
+
+        // every line is closed by its last separator, lines without any
+        // segment to display are left out
+        let mut outputs = outputs.into_iter();
+        let mut start = 0;
+        let lines: Vec<_> = self
+            .line_breaks
+            .iter()
+            .copied()
+            .chain([self.segments.len()])
+            .map(|end| {
+                let mut line: Vec<_> = outputs.by_ref().take(end - start).collect();
+                self.fit(&mut line, &self.segments[start..end], false);
+                start = end;
+                join(line, false)
+            })
+            .filter(|line| !line.is_empty())
+            .map(|line| self.render(&line))
+            .collect();
+        prompt += &lines.join("\n");
+
+        prompt
+    }
+
+    pub fn prompt(&self) {
+        print!("{}", self.render_prompt());
+    }
+
+    /// Prints the right prompt, for shells that have one.
+    pub fn right_prompt(&self) {
+        let mut outputs = self.render_segments(&self.right_segments);
+        self.fit(&mut outputs, &self.right_segments, true);
+        print!("{}", self.render_right(&join(outputs, true)));
+    }
+}
+
+// texts are not shortened below this width
+const MIN_TRUNCATED_WIDTH: usize = 12;
+
+// keep the first graphemes of text that fit in width columns, ellipsis included
+fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_owned();
+    }
+
+    let mut truncated = String::new();
+    let mut truncated_width = 0;
+    for grapheme in text.graphemes(true) {
+        truncated_width += grapheme.width();
+        if truncated_width + 1 > width {
+            break;
+        }
+        truncated += grapheme;
+    }
+    truncated + "…"
+}
+
+/// `$COLUMNS`, or the size of the terminal on stderr, stdout being captured
+/// by the shell.
+pub fn terminal_width() -> Option<usize> {
+    if let Some(columns) = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        return Some(columns);
+    }
+
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+        && size.ws_col > 0
+    {
+        Some(size.ws_col.into())
+    } else {
+        None
+    }
+}
+
+// one separator per displayed segment, before or after its sections
+fn join(outputs: Vec<Option<Vec<SegmentSection>>>, separator_first: bool) -> Vec<SegmentSection> {
+    outputs
+        .into_iter()
+        .flatten()
+        .flat_map(|mut sections| {
+            if separator_first {
+                sections.insert(0, SegmentSection::Seperator);
+            } else {
+                sections.push(SegmentSection::Seperator);
+            }
+            sections
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(text: &'static str, bg: u8) -> SegmentSection {
+        SegmentSection::Section {
+            text: text.into(),
+            bg: BackgroundColor::from_color_code(bg),
+            fg: ForegroundColor::from_color_code(15),
+            blinking: false,
+        }
+    }
+
+    #[test]
+    fn test_render_separators() {
+        let mut powerline = Powerline::new(Shell::Fish, Theme::try_from("default").unwrap());
+        powerline.set_separator(SeparatorStyle::Ascii);
+
+        let prompt = powerline.render(&[
+            section("~", 240),
+            SegmentSection::Seperator,
+            section("$", 236),
+            SegmentSection::Seperator,
+        ]);
+
+        assert_eq!(
+            prompt,
+            "\x1b[48;5;240m\x1b[38;5;15m ~\x1b[0m\x1b[48;5;240m \x1b[0m\x1b[38;5;240m\x1b[48;5;236m>\x1b[0m\
+             \x1b[48;5;236m\x1b[38;5;15m $\x1b[0m\x1b[48;5;236m \x1b[0m\x1b[38;5;236m>\x1b[0m "
+        );
+    }
+
+    #[test]
+    fn test_render_plain_separators() {
+        let powerline = Powerline::new(Shell::Fish, Theme::try_from("default").unwrap());
+
+        let prompt = powerline.render(&[
+            section("~", 240),
+            SegmentSection::Seperator,
+            section("$", 236),
+            SegmentSection::Seperator,
+        ]);
+
+        assert_eq!(
+            prompt,
+            "\x1b[48;5;240m\x1b[38;5;15m ~\x1b[0m \x1b[0m\x1b[48;5;236m\x1b[38;5;15m $\x1b[0m \x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_render_right_separators() {
+        let mut powerline = Powerline::new(Shell::Fish, Theme::try_from("default").unwrap());
+        powerline.set_separator(SeparatorStyle::Ascii);
+
+        let sections = join(
+            vec![
+                Some(vec![section("ns", 33)]),
+                None,
+                Some(vec![section("12:00", 240)]),
+            ],
+            true,
+        );
+
+        assert_eq!(powerline.width(&sections), 13);
+        assert_eq!(
+            powerline.render_right(&sections),
+            "\x1b[38;5;33m<\x1b[0m\x1b[48;5;33m\x1b[38;5;15m ns\x1b[0m\
+             \x1b[48;5;33m \x1b[38;5;240m<\x1b[0m\x1b[48;5;240m\x1b[38;5;15m 12:00\x1b[0m\
+             \x1b[48;5;240m \x1b[0m"
+        );
+    }
+
+    struct TextSegment(Option<&'static str>);
+
+    impl Segment for TextSegment {
+        fn name(&self) -> &'static str {
+            "text"
+        }
+
+        fn priority(&self) -> u8 {
+            // longer texts first
+            u8::MAX - self.0.map_or(0, |text| text.len() as u8)
+        }
+
+        fn output(&self, _: Shell, _: &Theme) -> Option<Vec<SegmentSection>> {
+            Some(vec![section(self.0?, 240)])
+        }
+    }
+
+    #[test]
+    fn test_render_lines() {
+        let mut powerline = Powerline::new(Shell::Fish, Theme::try_from("default").unwrap());
+        powerline.set_separator(SeparatorStyle::Ascii);
+        powerline.add_segment(Arc::new(TextSegment(Some("~"))));
+        powerline.add_newline();
+        powerline.add_segment(Arc::new(TextSegment(None)));
+        powerline.add_newline();
+        powerline.add_segment(Arc::new(TextSegment(Some("$"))));
+
+        let prompt = powerline.render_prompt();
+        let lines: Vec<_> = prompt.split('\n').collect();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(" ~") && lines[0].ends_with(">\x1b[0m "));
+        assert!(lines[1].contains(" $") && lines[1].ends_with(">\x1b[0m "));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("main", 12), "main");
+        assert_eq!(truncate("feature/long-branch-name", 12), "feature/lon…");
+        assert_eq!(truncate("日本語のブランチ", 7), "日本語…");
+    }
+
+    #[test]
+    fn test_fit() {
+        let theme = Theme::try_from("default").unwrap();
+        let mut powerline = Powerline::new(Shell::Fish, Theme::try_from("default").unwrap());
+        powerline.set_separator(SeparatorStyle::Ascii);
+        let segments: Vec<Arc<dyn Segment>> = vec![
+            Arc::new(TextSegment(Some("~/src/project"))),
+            Arc::new(TextSegment(Some("feature/long-branch-name"))),
+        ];
+        let outputs = || {
+            segments
+                .iter()
+                .map(|s| s.output(Shell::Fish, &theme))
+                .collect::<Vec<_>>()
+        };
+
+        // the branch segment has the lowest priority, it is dropped before
+        // the directory is shortened
+        powerline.set_max_width(Some(32));
+        let mut fitted = outputs();
+        powerline.fit(&mut fitted, &segments, false);
+        assert!(fitted[1].is_none());
+        assert!(matches!(&fitted[0].as_deref().unwrap()[0],
+            SegmentSection::Section { text, .. } if text == "~/src/project"));
+
+        // then the directory is shortened, as it does not fit on its own
+        powerline.set_max_width(Some(16));
+        let mut fitted = outputs();
+        powerline.fit(&mut fitted, &segments, false);
+        assert!(fitted[1].is_none());
+        assert!(matches!(&fitted[0].as_deref().unwrap()[0],
+            SegmentSection::Section { text, .. } if text == "~/src/proje…"));
+        // and the space after the last separator
+        assert_eq!(powerline.width(&join(fitted, false)) + 1, 16);
+    }
+
+    #[test]
+    fn test_fit_escaped() {
+        let mut powerline = Powerline::new(Shell::Bash, Theme::try_from("default").unwrap());
+        powerline.set_separator(SeparatorStyle::Ascii);
+        let segments: Vec<Arc<dyn Segment>> = vec![Arc::new(TextSegment(None))];
+        let mut outputs = vec![Some(vec![section(r"feature/\\$(id)-long-branch", 240)])];
+
+        // 25 columns displayed, the escape excluded
+        powerline.set_max_width(Some(29));
+        powerline.fit(&mut outputs, &segments, false);
+        assert!(matches!(&outputs[0].as_deref().unwrap()[0],
+            SegmentSection::Section { text, .. } if text == r"feature/\\$(id)-long-branch"));
+
+        powerline.set_max_width(Some(16));
+        powerline.fit(&mut outputs, &segments, false);
+        assert!(matches!(&outputs[0].as_deref().unwrap()[0],
+            SegmentSection::Section { text, .. } if text == r"feature/\\$(i…"));
+    }
 }
@@ -15,6 +15,10 @@ impl Segment for AwsSegment {
         "aws"
     }
 
+    fn priority(&self) -> u8 {
+        30
+    }
+
     fn output(&self, _shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
         let aws_profile = std::env::var("AWS_PROFILE").ok()?;
 
@@ -1,9 +1,11 @@
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tracing::{error, info};
 use ureq::Agent;
 use ureq::config::Config;
 
 use crate::configuration::ContainersConfiguration;
+use crate::daemon::{self, Cacheable, DaemonClient};
 use crate::segments::{Segment, SegmentSection};
 use crate::shell::Shell;
 use crate::theme::Theme;
@@ -13,11 +15,15 @@ const REQUEST_TIMEOUT_MS: u64 = 500;
 
 pub struct ContainersSegment<'a> {
     config: Option<&'a ContainersConfiguration>,
+    daemon: Option<&'a DaemonClient>,
 }
 
 impl<'a> ContainersSegment<'a> {
-    pub fn new(config: Option<&'a ContainersConfiguration>) -> Self {
-        Self { config }
+    pub fn new(
+        config: Option<&'a ContainersConfiguration>,
+        daemon: Option<&'a DaemonClient>,
+    ) -> Self {
+        Self { config, daemon }
     }
 }
 
@@ -61,14 +67,27 @@ fn list_containers<T: AsRef<str>>(url: T, timeout: Option<Duration>) -> Option<V
         .ok()
 }
 
-impl Segment for ContainersSegment<'_> {
-    fn name(&self) -> &'static str {
-        "containers"
-    }
+#[derive(Serialize, Deserialize)]
+pub struct ContainersQuery {
+    url: String,
+}
 
-    fn output(&self, _shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
-        let url = &self.config.as_ref()?.url;
-        let containers = list_containers(url, Some(Duration::from_millis(REQUEST_TIMEOUT_MS)))?;
+#[derive(Serialize, Deserialize)]
+pub struct ContainersData {
+    running: usize,
+    paused: usize,
+    exited: usize,
+    restarting: usize,
+}
+
+impl Cacheable for ContainersSegment<'_> {
+    const NAME: &'static str = "containers";
+    type Query = ContainersQuery;
+    type Data = ContainersData;
+
+    fn collect(query: &ContainersQuery) -> Option<ContainersData> {
+        let containers =
+            list_containers(&query.url, Some(Duration::from_millis(REQUEST_TIMEOUT_MS)))?;
 
         // status=(created, restarting, running, removing, paused, exited or dead)
         let (mut running, mut paused, mut exited, mut restarting) = (0, 0, 0, 0);
@@ -81,6 +100,36 @@ impl Segment for ContainersSegment<'_> {
                 _ => {}
             }
         }
+
+        Some(ContainersData {
+            running,
+            paused,
+            exited,
+            restarting,
+        })
+    }
+}
+
+impl Segment for ContainersSegment<'_> {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn priority(&self) -> u8 {
+        30
+    }
+
+    fn output(&self, _shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
+        let query = ContainersQuery {
+            url: self.config.as_ref()?.url.clone(),
+        };
+        let ContainersData {
+            running,
+            paused,
+            exited,
+            restarting,
+        } = daemon::fetch::<Self>(self.daemon, &query)?;
+
         if (running, paused, exited, restarting) == (0, 0, 0, 0) {
             return None;
         }
@@ -1,5 +1,9 @@
+use std::path::Path;
+
+use unicode_segmentation::UnicodeSegmentation;
+
 use crate::Shell;
-use crate::configuration::CwdConfiguration;
+use crate::configuration::{CwdAlias, CwdConfiguration};
 use crate::segments::{Segment, SegmentSection};
 use crate::theme::Theme;
 
@@ -13,29 +17,303 @@ impl<'a> CwdSegment<'a> {
     }
 }
 
+enum Root {
+    Home,
+    Filesystem,
+    // name of the git repository
+    Git(String),
+    Alias(String),
+}
+
+// the working directory as displayed: where it starts from, then the
+// directories below
+struct DisplayPath {
+    root: Root,
+    dirs: Vec<String>,
+}
+
+impl DisplayPath {
+    // the working directory as the shell sees it, symlinks unresolved
+    fn current(config: &CwdConfiguration) -> Option<Self> {
+        let cwd = std::env::var("PWD")
+            .ok()
+            .filter(|pwd| pwd.starts_with('/'))
+            .or_else(|| Some(std::env::current_dir().ok()?.to_string_lossy().into_owned()))?;
+        let home = std::env::var("HOME").ok().filter(|home| !home.is_empty());
+
+        Some(Self::new(
+            &cwd,
+            home.as_deref(),
+            config.git_root,
+            &config.aliases,
+        ))
+    }
+
+    fn new(cwd: &str, home: Option<&str>, git_root: bool, aliases: &[CwdAlias]) -> Self {
+        // a home at the filesystem root is no home at all
+        let home = home
+            .map(|home| home.trim_end_matches('/'))
+            .filter(|home| !home.is_empty());
+        let split = |path: &str| -> Vec<String> {
+            path.split('/')
+                .filter(|dir| !dir.is_empty())
+                .map(ToOwned::to_owned)
+                .collect()
+        };
+
+        let alias = aliases
+            .iter()
+            .filter_map(|alias| {
+                let path = match (alias.path.strip_prefix('~'), home) {
+                    (Some(rest), Some(home)) => format!("{}{}", home, rest),
+                    _ => alias.path.clone(),
+                };
+                let path = path.trim_end_matches('/').to_owned();
+                let rest = cwd.strip_prefix(&path)?;
+                (rest.is_empty() || rest.starts_with('/')).then_some((path.len(), alias, rest))
+            })
+            .max_by_key(|&(len, ..)| len);
+        if let Some((_, alias, rest)) = alias {
+            return Self {
+                root: Root::Alias(alias.alias.clone()),
+                dirs: split(rest),
+            };
+        }
+
+        if git_root
+            && let Some(repository) = Path::new(cwd)
+                .ancestors()
+                .find(|dir| dir.join(".git").exists())
+            && let Some(name) = repository.file_name()
+        {
+            let relative = Path::new(cwd)
+                .strip_prefix(repository)
+                .unwrap_or(Path::new(""));
+            return Self {
+                root: Root::Git(name.to_string_lossy().into_owned()),
+                dirs: split(&relative.to_string_lossy()),
+            };
+        }
+
+        match home {
+            Some(home) if cwd == home => Self {
+                root: Root::Home,
+                dirs: vec![],
+            },
+            Some(home) if cwd.starts_with(&format!("{}/", home)) => Self {
+                root: Root::Home,
+                dirs: split(&cwd[home.len()..]),
+            },
+            _ => Self {
+                root: Root::Filesystem,
+                dirs: split(cwd),
+            },
+        }
+    }
+
+    fn root_name(&self) -> &str {
+        match self.root {
+            Root::Home => "~",
+            Root::Filesystem => "/",
+            Root::Git(ref name) | Root::Alias(ref name) => name,
+        }
+    }
+
+    // keep the last max_depth directories
+    fn truncate(&mut self, max_depth: usize) {
+        if self.dirs.len() > max_depth {
+            self.dirs.drain(..self.dirs.len() - max_depth);
+            self.dirs.insert(0, "…".into());
+        }
+    }
+
+    // ~/src/opsline/src -> ~/s/o/src
+    fn shorten(&mut self) {
+        let parents = self.dirs.len().saturating_sub(1);
+        for dir in &mut self.dirs[..parents] {
+            let keep = if dir.starts_with('.') { 2 } else { 1 };
+            *dir = dir.graphemes(true).take(keep).collect();
+        }
+    }
+
+    fn text(&self) -> String {
+        let dirs = self.dirs.join("/");
+        match self.root {
+            _ if dirs.is_empty() => self.root_name().to_owned(),
+            Root::Home => format!("~/{}", dirs),
+            Root::Filesystem => format!("/{}", dirs),
+            Root::Git(ref name) => format!("{}:{}", name, dirs),
+            Root::Alias(ref name) => format!("{}/{}", name, dirs),
+        }
+    }
+}
+
 impl Segment for CwdSegment<'_> {
     fn name(&self) -> &'static str {
         "cwd"
     }
 
+    fn priority(&self) -> u8 {
+        90
+    }
+
     fn output(&self, shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
-        let text = if self.config.dironly {
-            match shell {
-                Shell::Bash => r"\W",
-                Shell::Zsh => "%1d",
-            }
-        } else {
-            match shell {
-                Shell::Bash => r"\w",
-                Shell::Zsh => "%d",
+        let mut path = DisplayPath::current(self.config)?;
+        let aliased = matches!(path.root, Root::Alias(_));
+
+        if self.config.dironly {
+            let (text, bg, fg) = match path.dirs.last() {
+                Some(dir) => (dir.as_str(), theme.cwd_bg, theme.cwd_fg),
+                None if aliased => (path.root_name(), theme.cwd_alias_bg, theme.cwd_alias_fg),
+                None => (path.root_name(), theme.cwd_bg, theme.cwd_fg),
+            };
+            return Some(vec![SegmentSection::Section {
+                text: shell.escape(text).into_owned().into(),
+                bg,
+                fg,
+                blinking: false,
+            }]);
+        }
+
+        if let Some(max_depth) = self.config.max_depth {
+            path.truncate(max_depth);
+        }
+        if self.config.shorten {
+            path.shorten();
+        }
+
+        // the alias in its own colours, followed by the directories below it
+        if aliased && !self.config.split {
+            let mut sections = vec![SegmentSection::Section {
+                text: shell.escape(path.root_name()).into_owned().into(),
+                bg: theme.cwd_alias_bg,
+                fg: theme.cwd_alias_fg,
+                blinking: false,
+            }];
+            if !path.dirs.is_empty() {
+                sections.push(SegmentSection::Section {
+                    text: shell.escape(&path.dirs.join("/")).into_owned().into(),
+                    bg: theme.cwd_bg,
+                    fg: theme.cwd_fg,
+                    blinking: false,
+                });
             }
+            return Some(sections);
+        }
+
+        if !self.config.split {
+            return Some(vec![SegmentSection::Section {
+                text: shell.escape(&path.text()).into_owned().into(),
+                bg: theme.cwd_bg,
+                fg: theme.cwd_fg,
+                blinking: false,
+            }]);
+        }
+
+        // parent directories in the path colours, the current one in the cwd colours
+        let names: Vec<_> = std::iter::once(path.root_name())
+            .chain(path.dirs.iter().map(String::as_str))
+            .collect();
+        let sections = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let (bg, fg) = if i == 0 && aliased {
+                    (theme.cwd_alias_bg, theme.cwd_alias_fg)
+                } else if i == names.len() - 1 {
+                    (theme.cwd_bg, theme.cwd_fg)
+                } else {
+                    (theme.cwd_path_bg, theme.cwd_path_fg)
+                };
+                SegmentSection::Section {
+                    text: shell.escape(name).into_owned().into(),
+                    bg,
+                    fg,
+                    blinking: false,
+                }
+            })
+            .collect();
+
+        Some(sections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(cwd: &str, max_depth: Option<usize>, shorten: bool) -> String {
+        let mut path = DisplayPath::new(cwd, Some("/home/me"), false, &[]);
+        if let Some(max_depth) = max_depth {
+            path.truncate(max_depth);
+        }
+        if shorten {
+            path.shorten();
+        }
+        path.text()
+    }
+
+    #[test]
+    fn test_display_path() {
+        assert_eq!(display("/home/me", None, false), "~");
+        assert_eq!(
+            display("/home/me/src/opsline", None, false),
+            "~/src/opsline"
+        );
+        assert_eq!(display("/home/meh", None, false), "/home/meh");
+        assert_eq!(display("/", None, false), "/");
+        assert_eq!(
+            display("/usr/local/share/man", Some(2), false),
+            "/…/share/man"
+        );
+        assert_eq!(
+            display("/home/me/.config/opsline/themes", None, true),
+            "~/.c/o/themes"
+        );
+        assert_eq!(display("/home/me/a/b/c/d", Some(3), true), "~/…/b/c/d");
+    }
+
+    #[test]
+    fn test_display_path_root_home() {
+        let display = |cwd, home| DisplayPath::new(cwd, Some(home), false, &[]).text();
+
+        assert_eq!(display("/etc", "/"), "/etc");
+        assert_eq!(display("/", "/"), "/");
+        assert_eq!(display("/home/me/src", "/home/me/"), "~/src");
+    }
+
+    #[test]
+    fn test_display_path_alias() {
+        let alias = |path: &str, alias: &str| CwdAlias {
+            path: path.into(),
+            alias: alias.into(),
         };
+        let aliases = [
+            alias("~/work/platform", "PLAT"),
+            alias("/home/me/work/platform/infra/", "INFRA"),
+        ];
+        let display = |cwd| DisplayPath::new(cwd, Some("/home/me"), false, &aliases).text();
+
+        assert_eq!(display("/home/me/work/platform"), "PLAT");
+        assert_eq!(display("/home/me/work/platform/apps/web"), "PLAT/apps/web");
+        assert_eq!(display("/home/me/work/platform/infra/envs"), "INFRA/envs");
+        assert_eq!(display("/home/me/work/platformer"), "~/work/platformer");
+    }
+
+    #[test]
+    fn test_display_path_git_root() {
+        let repository = std::env::temp_dir().join(format!("opsline-cwd-{}", std::process::id()));
+        std::fs::create_dir_all(repository.join(".git")).unwrap();
+        std::fs::create_dir_all(repository.join("src/segments")).unwrap();
+
+        let cwd = repository.join("src/segments");
+        let path = DisplayPath::new(&cwd.to_string_lossy(), None, true, &[]);
+        let name = repository.file_name().unwrap().to_string_lossy();
+        assert_eq!(path.text(), format!("{}:src/segments", name));
+
+        let path = DisplayPath::new(&repository.to_string_lossy(), None, true, &[]);
+        assert_eq!(path.text(), name);
 
-        Some(Vec::from([SegmentSection::Section {
-            text: text.into(),
-            bg: theme.cwd_bg,
-            fg: theme.cwd_fg,
-            blinking: false,
-        }]))
+        std::fs::remove_dir_all(repository).unwrap();
     }
 }
@@ -15,6 +15,10 @@ impl Segment for DevwrapSegment {
         "devwrap"
     }
 
+    fn priority(&self) -> u8 {
+        30
+    }
+
     fn output(&self, _shell: Shell, theme: &Theme) -> Option<Vec<super::SegmentSection>> {
         if std::env::var("DEVWRAP").is_ok() {
             Some(Vec::from([SegmentSection::Section {
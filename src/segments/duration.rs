@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use crate::configuration::DurationConfiguration;
+use crate::segments::{Segment, SegmentSection};
+use crate::shell::Shell;
+use crate::theme::Theme;
+
+pub struct DurationSegment<'a> {
+    config: &'a DurationConfiguration,
+    duration: Option<Duration>,
+}
+
+impl<'a> DurationSegment<'a> {
+    pub fn new(config: &'a DurationConfiguration, duration: Option<Duration>) -> Self {
+        Self { config, duration }
+    }
+}
+
+// 1h2m3s, 1m32s, 4s or 850ms
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    if hours > 0 {
+        format!("{}h{}m{}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{}s", minutes, seconds)
+    } else if seconds > 0 {
+        format!("{}s", seconds)
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+impl Segment for DurationSegment<'_> {
+    fn name(&self) -> &'static str {
+        "duration"
+    }
+
+    fn output(&self, _shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
+        let duration = self
+            .duration
+            .filter(|d| d.as_millis() >= u128::from(self.config.threshold_ms))?;
+
+        Some(Vec::from([SegmentSection::Section {
+            text: format!("⧗ {}", format_duration(duration)).into(),
+            bg: theme.duration_bg,
+            fg: theme.duration_fg,
+            blinking: false,
+        }]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(850)), "850ms");
+        assert_eq!(format_duration(Duration::from_millis(4200)), "4s");
+        assert_eq!(format_duration(Duration::from_secs(92)), "1m32s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h2m3s");
+    }
+}
@@ -0,0 +1,115 @@
+use crate::configuration::ExitConfiguration;
+use crate::segments::{Segment, SegmentSection};
+use crate::shell::Shell;
+use crate::theme::Theme;
+
+pub struct ExitSegment<'a> {
+    config: &'a ExitConfiguration,
+    last_exit_code: Option<i32>,
+}
+
+impl<'a> ExitSegment<'a> {
+    pub fn new(config: &'a ExitConfiguration, last_exit_code: Option<i32>) -> Self {
+        Self {
+            config,
+            last_exit_code,
+        }
+    }
+}
+
+fn signal_name(signal: i32) -> Option<&'static str> {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGCHLD => "SIGCHLD",
+        libc::SIGCONT => "SIGCONT",
+        libc::SIGSTOP => "SIGSTOP",
+        libc::SIGTSTP => "SIGTSTP",
+        libc::SIGTTIN => "SIGTTIN",
+        libc::SIGTTOU => "SIGTTOU",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGSYS => "SIGSYS",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+impl Segment for ExitSegment<'_> {
+    fn name(&self) -> &'static str {
+        "exit"
+    }
+
+    fn priority(&self) -> u8 {
+        80
+    }
+
+    fn output(&self, _shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
+        let code = self.last_exit_code.filter(|&code| code != 0)?;
+
+        // shells report a command killed by signal N as 128 + N
+        let signal = if self.config.signal_name && code > 128 {
+            signal_name(code - 128)
+        } else {
+            None
+        };
+
+        let text = match signal {
+            Some(signal) => format!("✘ {} {}", code, signal),
+            None => format!("✘ {}", code),
+        };
+
+        Some(Vec::from([SegmentSection::Section {
+            text: text.into(),
+            bg: theme.exit_bg,
+            fg: theme.exit_fg,
+            blinking: false,
+        }]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(signal_name: bool, last_exit_code: Option<i32>) -> Option<String> {
+        let config = ExitConfiguration { signal_name };
+        let theme = Theme::try_from("default").unwrap();
+        let sections = ExitSegment::new(&config, last_exit_code).output(Shell::Bash, &theme)?;
+        match sections.into_iter().next()? {
+            SegmentSection::Section { text, .. } => Some(text.into_owned()),
+            SegmentSection::Seperator => None,
+        }
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(text(true, None), None);
+        assert_eq!(text(true, Some(0)), None);
+        assert_eq!(text(true, Some(1)).as_deref(), Some("✘ 1"));
+        assert_eq!(text(true, Some(128)).as_deref(), Some("✘ 128"));
+    }
+
+    #[test]
+    fn test_exit_signal() {
+        assert_eq!(text(true, Some(130)).as_deref(), Some("✘ 130 SIGINT"));
+        assert_eq!(text(true, Some(137)).as_deref(), Some("✘ 137 SIGKILL"));
+        assert_eq!(text(false, Some(130)).as_deref(), Some("✘ 130"));
+        // no signal 127 exists, so only the code is shown
+        assert_eq!(text(true, Some(255)).as_deref(), Some("✘ 255"));
+    }
+}
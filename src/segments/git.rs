@@ -1,24 +1,468 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
 use crate::Shell;
+use crate::configuration::{Forge, GitConfiguration, GitIndicator};
+use crate::daemon::{self, Cacheable, DaemonClient};
 use crate::segments::{Segment, SegmentSection};
 use crate::theme::Theme;
-use git2::{BranchType, Repository};
-use tracing::{error, info};
+use git2::{
+    BranchType, DescribeFormatOptions, DescribeOptions, FileMode, Repository, RepositoryState,
+    Status, StatusOptions, Worktree,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
 
-pub struct GitSegment;
+pub struct GitSegment<'a> {
+    config: &'a GitConfiguration,
+    daemon: Option<&'a DaemonClient>,
+}
 
-impl GitSegment {
-    pub fn new() -> Self {
-        Self {}
+impl<'a> GitSegment<'a> {
+    pub fn new(config: &'a GitConfiguration, daemon: Option<&'a DaemonClient>) -> Self {
+        Self { config, daemon }
     }
 }
 
-impl Segment for GitSegment {
-    fn name(&self) -> &'static str {
-        "git"
+#[derive(Serialize, Deserialize)]
+struct StatusLimits {
+    untracked: bool,
+    max_entries: Option<usize>,
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GitQuery {
+    cwd: PathBuf,
+    // invalidate cached data on commit, checkout, staging or stash
+    head: Option<SystemTime>,
+    index: Option<SystemTime>,
+    stash: Option<SystemTime>,
+    base_branch: Option<String>,
+    // look for the oldest uncommitted change
+    dirty_since: bool,
+    status: StatusLimits,
+}
+
+impl GitQuery {
+    fn new(config: &GitConfiguration) -> Option<Self> {
+        let cwd = std::env::current_dir().ok()?;
+        let git_dir = Repository::discover_path(&cwd, None::<&Path>).ok()?;
+        // linked worktrees share the stash of the main repository
+        let common_dir = Repository::open(&git_dir).ok()?.commondir().to_owned();
+        let modified = |path: PathBuf| path.metadata().and_then(|m| m.modified()).ok();
+
+        // working tree edits are not part of the key, they show once the TTL expires
+        Some(Self {
+            head: modified(git_dir.join("HEAD")),
+            index: modified(git_dir.join("index")),
+            stash: modified(common_dir.join("logs/refs/stash")),
+            base_branch: config.base_branch.clone(),
+            dirty_since: config.dirty_warning_hours.is_some(),
+            status: StatusLimits {
+                untracked: !config.skip_untracked,
+                max_entries: config.status_max_entries,
+                timeout_ms: config.status_timeout_ms,
+            },
+            cwd,
+        })
     }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Upstream {
+    NotConfigured,
+    // configured, but the remote branch does not exist anymore
+    Gone,
+    Tracking(String),
+}
+
+// host, owner and name of a repository, from its remote URL
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct RemoteRepository {
+    host: String,
+    owner: String,
+    name: String,
+}
+
+impl RemoteRepository {
+    // https://host/owner/repo.git, ssh://git@host:22/owner/repo.git or git@host:owner/repo.git
+    fn parse(url: &str) -> Option<Self> {
+        let (host, path) = match url.split_once("://") {
+            Some((_, rest)) => {
+                let (authority, path) = rest.split_once('/')?;
+                let host = authority.rsplit('@').next()?;
+                (host.split(':').next()?, path)
+            }
+            None => {
+                let (authority, path) = url.split_once(':')?;
+                (authority.rsplit('@').next()?, path)
+            }
+        };
+
+        // gitlab groups may be nested: group/subgroup/repo
+        let path = path.trim_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        let (owner, name) = path.rsplit_once('/')?;
+        if host.is_empty() || owner.is_empty() || name.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            host: host.to_lowercase(),
+            owner: owner.to_owned(),
+            name: name.to_owned(),
+        })
+    }
+
+    fn forge(&self, hosts: &BTreeMap<String, Forge>) -> Option<Forge> {
+        if let Some(forge) = hosts.get(&self.host) {
+            return Some(*forge);
+        }
+
+        match self.host.as_str() {
+            "github.com" => Some(Forge::Github),
+            "gitlab.com" => Some(Forge::Gitlab),
+            "bitbucket.org" => Some(Forge::Bitbucket),
+            "gitea.com" | "codeberg.org" => Some(Forge::Gitea),
+            host if host.contains("github") => Some(Forge::Github),
+            host if host.contains("gitlab") => Some(Forge::Gitlab),
+            host if host.contains("gitea") => Some(Forge::Gitea),
+            _ => None,
+        }
+    }
+}
+
+// 3d, 2h, 5m or now
+fn format_age(age: Duration) -> String {
+    let minutes = age.as_secs() / 60;
+    let (hours, days) = (minutes / 60, minutes / 60 / 24);
+
+    if days > 0 {
+        format!("{}d", days)
+    } else if hours > 0 {
+        format!("{}h", hours)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        "now".to_owned()
+    }
+}
+
+fn forge_icon(forge: Option<Forge>) -> &'static str {
+    match forge {
+        Some(Forge::Bitbucket) => "\u{f171}",
+        Some(Forge::Gitea) => "\u{f0f4}",
+        Some(Forge::Github) => "\u{f09b}",
+        Some(Forge::Gitlab) => "\u{f296}",
+        None => "\u{e702}",
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct GitData {
+    branch: String,
+    // repository the origin remote points to
+    origin: Option<RemoteRepository>,
+    // name of the linked worktree
+    worktree: Option<String>,
+    // name of the superproject, in a submodule
+    superproject: Option<String>,
+    sparse: bool,
+    // submodules with new commits or a dirty working tree
+    dirty_submodules: usize,
+    // operation in progress: REBASE 3/7, MERGING, ...
+    state: Option<String>,
+    // None when HEAD is detached
+    upstream: Option<Upstream>,
+    ahead: usize,
+    behind: usize,
+    // whether ahead and behind were computed
+    compared: bool,
+    // commits ahead of and behind the base branch
+    base: Option<(String, usize, usize)>,
+    stashes: usize,
+    // HEAD commit time
+    committed: Option<SystemTime>,
+    // modification time of the oldest changed file
+    dirty_since: Option<SystemTime>,
+    staged: usize,
+    unstaged: usize,
+    untracked: usize,
+    conflicted: usize,
+    renamed: usize,
+    deleted: usize,
+    // file counts are partial, a status limit was hit
+    truncated: bool,
+}
+
+// read from the files git keeps in the git directory during a rebase
+fn rebase_file(repo: &Repository, name: &str) -> Option<String> {
+    ["rebase-merge", "rebase-apply"].iter().find_map(|dir| {
+        let content = std::fs::read_to_string(repo.path().join(dir).join(name)).ok()?;
+        Some(content.trim().to_owned())
+    })
+}
+
+fn operation_state(repo: &Repository) -> Option<String> {
+    // rebase-merge counts steps in msgnum/end, rebase-apply in next/last
+    let progress = || {
+        let step = rebase_file(repo, "msgnum").or_else(|| rebase_file(repo, "next"))?;
+        let total = rebase_file(repo, "end").or_else(|| rebase_file(repo, "last"))?;
+        Some(format!("{}/{}", step, total))
+    };
+    let with_progress = |name: &str| match progress() {
+        Some(progress) => format!("{} {}", name, progress),
+        None => name.to_owned(),
+    };
+
+    let state = match repo.state() {
+        RepositoryState::Clean => return None,
+        RepositoryState::Merge => "MERGING".to_owned(),
+        RepositoryState::Revert | RepositoryState::RevertSequence => "REVERTING".to_owned(),
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => {
+            "CHERRY-PICKING".to_owned()
+        }
+        RepositoryState::Bisect => "BISECTING".to_owned(),
+        RepositoryState::Rebase
+        | RepositoryState::RebaseInteractive
+        | RepositoryState::RebaseMerge => with_progress("REBASE"),
+        RepositoryState::ApplyMailbox => with_progress("AM"),
+        RepositoryState::ApplyMailboxOrRebase => with_progress("AM/REBASE"),
+    };
+
+    Some(state)
+}
+
+// a file counts in every bucket that applies, like `git status --porcelain=v2`
+fn classify(status: Status, data: &mut GitData) {
+    if status.is_conflicted() {
+        data.conflicted += 1;
+        return;
+    }
+    if status.is_wt_new() {
+        data.untracked += 1;
+    }
+
+    if status.intersects(
+        Status::INDEX_NEW
+            | Status::INDEX_MODIFIED
+            | Status::INDEX_DELETED
+            | Status::INDEX_RENAMED
+            | Status::INDEX_TYPECHANGE,
+    ) {
+        data.staged += 1;
+    }
+    if status.intersects(
+        Status::WT_MODIFIED | Status::WT_DELETED | Status::WT_RENAMED | Status::WT_TYPECHANGE,
+    ) {
+        data.unstaged += 1;
+    }
+    if status.intersects(Status::INDEX_RENAMED | Status::WT_RENAMED) {
+        data.renamed += 1;
+    }
+    if status.intersects(Status::INDEX_DELETED | Status::WT_DELETED) {
+        data.deleted += 1;
+    }
+}
+
+// a changed file, or a submodule with new commits or a dirty working tree
+struct StatusEntry {
+    status: Status,
+    path: PathBuf,
+    submodule: bool,
+}
+
+fn libgit2_status(repo: &Repository, limits: &StatusLimits) -> Option<Vec<StatusEntry>> {
+    // untracked directories count as one entry, like git status
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(limits.untracked)
+        .recurse_untracked_dirs(false)
+        .include_ignored(false)
+        .renames_head_to_index(true)
+        .renames_index_to_workdir(true);
+
+    let statuses = repo.statuses(Some(&mut options)).ok()?;
+    Some(
+        statuses
+            .iter()
+            .map(|entry| {
+                // the new path of renamed files
+                let path = entry
+                    .index_to_workdir()
+                    .or_else(|| entry.head_to_index())
+                    .and_then(|diff| diff.new_file().path_bytes())
+                    .unwrap_or(entry.path_bytes());
+                // untracked repositories are not submodules
+                let submodule = !entry.status().is_wt_new()
+                    && [entry.head_to_index(), entry.index_to_workdir()]
+                        .into_iter()
+                        .flatten()
+                        .any(|diff| diff.new_file().mode() == FileMode::Commit);
+                StatusEntry {
+                    status: entry.status(),
+                    path: String::from_utf8_lossy(path).into_owned().into(),
+                    submodule,
+                }
+            })
+            .collect(),
+    )
+}
+
+// libgit2 supports neither core.fsmonitor nor core.untrackedCache, git does
+fn fsmonitor(repo: &Repository) -> bool {
+    let Ok(config) = repo.config() else {
+        return false;
+    };
+    // booleans, or the path of a hook for core.fsmonitor
+    let enabled = |name| {
+        config
+            .get_bool(name)
+            .unwrap_or_else(|_| config.get_string(name).is_ok_and(|value| !value.is_empty()))
+    };
+
+    enabled("core.fsmonitor") || enabled("core.untrackedCache")
+}
+
+// a `git status --porcelain=v2 -z` record
+fn porcelain_status(record: &[u8]) -> Option<StatusEntry> {
+    let index = |x| match x {
+        b'M' => Status::INDEX_MODIFIED,
+        b'A' | b'C' => Status::INDEX_NEW,
+        b'D' => Status::INDEX_DELETED,
+        b'R' => Status::INDEX_RENAMED,
+        b'T' => Status::INDEX_TYPECHANGE,
+        _ => Status::empty(),
+    };
+    let worktree = |y| match y {
+        b'M' => Status::WT_MODIFIED,
+        b'D' => Status::WT_DELETED,
+        b'R' | b'C' => Status::WT_RENAMED,
+        b'T' => Status::WT_TYPECHANGE,
+        _ => Status::empty(),
+    };
+
+    // the path is the last field, submodules are flagged `S` in the third one
+    let entry = |status, fields| {
+        let path = record.splitn(fields, |&b| b == b' ').nth(fields - 1)?;
+        Some(StatusEntry {
+            status,
+            path: String::from_utf8_lossy(path).into_owned().into(),
+            submodule: fields > 2
+                && record
+                    .split(|&b| b == b' ')
+                    .nth(2)
+                    .is_some_and(|sub| sub.starts_with(b"S")),
+        })
+    };
+
+    match record {
+        [b'1', b' ', x, y, ..] => entry(index(*x) | worktree(*y), 9),
+        [b'2', b' ', x, y, ..] => entry(index(*x) | worktree(*y), 10),
+        [b'u', b' ', ..] => entry(Status::CONFLICTED, 11),
+        [b'?', b' ', ..] => entry(Status::WT_NEW, 2),
+        _ => None,
+    }
+}
 
-    fn output(&self, _shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
-        let repo = Repository::discover(".").ok()?;
+// git status, stopped once a limit is hit. Returns the statuses read so far
+// and whether they are complete.
+fn git_status(workdir: &Path, limits: &StatusLimits) -> Option<(Vec<StatusEntry>, bool)> {
+    let deadline = limits
+        .timeout_ms
+        .map(|timeout_ms| Instant::now() + Duration::from_millis(timeout_ms));
+
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(workdir)
+        .args(["--no-optional-locks", "status", "--porcelain=v2", "-z"])
+        .args(if limits.untracked {
+            ["--untracked-files=normal", "--ignore-submodules=none"]
+        } else {
+            ["--untracked-files=no", "--ignore-submodules=untracked"]
+        })
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| error!("failed to run git status: {}", e))
+        .ok()?;
+
+    let stdout = child.stdout.take()?;
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut records = BufReader::new(stdout).split(b'\0');
+        while let Some(Ok(record)) = records.next() {
+            // renamed and copied entries are followed by the original path
+            if record.starts_with(b"2 ") {
+                records.next();
+            }
+            if let Some(status) = porcelain_status(&record)
+                && tx.send(status).is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let mut statuses = vec![];
+    let complete = loop {
+        let received = match deadline {
+            Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(mpsc::RecvTimeoutError::from),
+        };
+
+        match received {
+            Ok(status) => statuses.push(status),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break true,
+            Err(mpsc::RecvTimeoutError::Timeout) => break false,
+        }
+        if limits.max_entries.is_some_and(|max| statuses.len() > max) {
+            statuses.pop();
+            break false;
+        }
+    };
+
+    if !complete {
+        debug!("git status limit hit, stopping");
+        let _ = child.kill();
+    }
+    let success = child.wait().is_ok_and(|status| status.success());
+
+    (success || !complete).then_some((statuses, complete))
+}
+
+// submodules keep their git dir in the one of their superproject:
+// <superproject>/.git/modules/<name>, or .../modules/<parent>/modules/<name>
+// when nested. A repository that merely sits in a directory named modules
+// has no git dir above it.
+fn superproject(repo: &Repository) -> Option<String> {
+    let git_dir = repo
+        .path()
+        .ancestors()
+        .filter(|dir| dir.file_name().is_some_and(|name| name == "modules"))
+        .filter_map(|modules| modules.parent())
+        .find(|git_dir| git_dir.join("HEAD").is_file())?;
+    let name = match git_dir.file_name()? {
+        name if name == ".git" => git_dir.parent()?.file_name()?,
+        name => name,
+    };
+    Some(name.to_string_lossy().into_owned())
+}
+
+impl Cacheable for GitSegment<'_> {
+    const NAME: &'static str = "git";
+    type Query = GitQuery;
+    type Data = GitData;
+
+    fn collect(query: &GitQuery) -> Option<GitData> {
+        let repo = Repository::discover(&query.cwd).ok()?;
         info!("repository found at {}", repo.path().to_string_lossy());
 
         let current_branch =
@@ -30,119 +474,874 @@ impl Segment for GitSegment {
                 });
 
         let mut branch_name = None;
+        let mut upstream_state = None;
         let mut upstream = None;
         let mut local = None;
         if let Some(Ok((branch, _))) = current_branch {
-            upstream = branch.upstream().ok().and_then(|b| b.get().target());
             local = branch.get().target();
             if let Ok(Some(name)) = branch.name() {
+                let configured = repo.config().is_ok_and(|config| {
+                    config.get_string(&format!("branch.{}.merge", name)).is_ok()
+                });
+                // the remote or the remote branch may not exist anymore
+                let reference = branch
+                    .get()
+                    .name()
+                    .ok()
+                    .and_then(|refname| repo.branch_upstream_name(refname).ok())
+                    .and_then(|upstream| repo.find_reference(upstream.as_str().ok()?).ok());
+
+                upstream_state = Some(match reference {
+                    _ if !configured => Upstream::NotConfigured,
+                    Some(reference) => {
+                        upstream = reference.target();
+                        Upstream::Tracking(reference.shorthand().unwrap_or_default().to_owned())
+                    }
+                    None => Upstream::Gone,
+                });
                 branch_name = Some(name.to_owned());
             }
+        } else if let Some(head_name) = rebase_file(&repo, "head-name") {
+            // HEAD is detached while rebasing, show the branch being rebased
+            branch_name = Some(
+                head_name
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(&head_name)
+                    .to_owned(),
+            );
         } else if let Ok(head) = repo.head() {
-            // detached state
-            if let Some(oid) = head.target() {
+            // detached state, the tag at HEAD or `git describe --tags` output
+            let describe = repo
+                .describe(DescribeOptions::new().describe_tags())
+                .and_then(|describe| {
+                    describe.format(Some(DescribeFormatOptions::new().abbreviated_size(7)))
+                });
+            if let Ok(describe) = describe {
+                branch_name = Some(describe);
+            } else if let Some(oid) = head.target() {
                 let mut oid = oid.to_string();
                 oid.truncate(7);
                 branch_name = Some(oid);
             }
         }
 
-        let mut sections = Vec::from([SegmentSection::Section {
-            text: format!(" {}", branch_name?).into(),
-            bg: theme.git_branch_bg,
-            fg: theme.git_branch_fg,
-            blinking: false,
-        }]);
+        let mut data = GitData {
+            branch: branch_name?,
+            state: operation_state(&repo),
+            upstream: upstream_state,
+            origin: repo
+                .find_remote("origin")
+                .ok()
+                .and_then(|remote| RemoteRepository::parse(remote.url().ok()?)),
+            worktree: repo
+                .is_worktree()
+                .then(|| Worktree::open_from_repository(&repo).ok())
+                .flatten()
+                .and_then(|worktree| Some(worktree.name().ok()??.to_owned())),
+            superproject: superproject(&repo),
+            sparse: repo
+                .config()
+                .and_then(|config| config.get_bool("core.sparseCheckout"))
+                .unwrap_or(false),
+            // one reflog entry per stash
+            stashes: repo.reflog("refs/stash").map_or(0, |reflog| reflog.len()),
+            committed: repo
+                .head()
+                .and_then(|head| head.peel_to_commit())
+                .ok()
+                .and_then(|commit| u64::try_from(commit.time().seconds()).ok())
+                .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds)),
+            ..Default::default()
+        };
 
         if let (Some(local), Some(upstream)) = (local, upstream)
             && let Ok((ahead, behind)) = repo.graph_ahead_behind(local, upstream)
         {
-            if ahead > 0 {
-                sections.push(SegmentSection::Section {
-                    text: format!("{}⬆", ahead).into(),
-                    bg: theme.git_ahead_bg,
-                    fg: theme.git_ahead_fg,
-                    blinking: false,
-                });
-            }
-            if behind > 0 {
-                sections.push(SegmentSection::Section {
-                    text: format!("{}⬇", behind).into(),
-                    bg: theme.git_behind_bg,
-                    fg: theme.git_behind_fg,
-                    blinking: false,
-                });
-            }
+            data.ahead = ahead;
+            data.behind = behind;
+            data.compared = true;
         }
 
-        let mut modified = 0;
-        let mut staged = 0;
-        let mut untracked = 0;
-        let mut conflicted = 0;
-        match repo.statuses(None) {
-            Ok(statuses) => {
-                for status in statuses.iter() {
-                    let status = status.status();
-                    if status.is_wt_modified()
-                        || status.is_wt_deleted()
-                        || status.is_wt_typechange()
-                    {
-                        modified += 1;
-                    } else if status.is_index_new()
-                        || status.is_index_modified()
-                        || status.is_index_deleted()
-                        || status.is_index_renamed()
-                        || status.is_index_typechange()
-                    {
-                        staged += 1;
-                    } else if status.is_wt_new() {
-                        untracked += 1;
-                    } else if status.is_conflicted() {
-                        conflicted += 1;
-                    }
+        if let Some(ref base_branch) = query.base_branch
+            && let Some(head) = repo.head().ok().and_then(|head| head.target())
+            && let Ok(base) = repo
+                .revparse_single(base_branch)
+                .and_then(|base| base.peel_to_commit())
+            && let Ok((ahead, behind)) = repo.graph_ahead_behind(head, base.id())
+        {
+            data.base = Some((base_branch.clone(), ahead, behind));
+        }
+
+        let bounded = query.status.max_entries.is_some() || query.status.timeout_ms.is_some();
+        let statuses = if bounded || fsmonitor(&repo) {
+            repo.workdir()
+                .and_then(|workdir| git_status(workdir, &query.status))
+        } else {
+            libgit2_status(&repo, &query.status).map(|statuses| (statuses, true))
+        };
+
+        match statuses {
+            Some((statuses, complete)) => {
+                if query.dirty_since
+                    && let Some(workdir) = repo.workdir()
+                {
+                    // untracked files may be old copies, deleted ones have no
+                    // modification time
+                    data.dirty_since = statuses
+                        .iter()
+                        .filter(|entry| !entry.status.is_wt_new())
+                        .filter_map(|entry| workdir.join(&entry.path).symlink_metadata().ok())
+                        .filter_map(|metadata| metadata.modified().ok())
+                        .min();
                 }
+                data.dirty_submodules = statuses.iter().filter(|entry| entry.submodule).count();
+                for entry in statuses {
+                    classify(entry.status, &mut data);
+                }
+                data.truncated = !complete;
             }
-            Err(_) => {
+            None => {
                 error!("failed to get git repository status");
             }
         }
 
-        if staged > 0 {
+        Some(data)
+    }
+}
+
+impl Segment for GitSegment<'_> {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn priority(&self) -> u8 {
+        70
+    }
+
+    fn output(&self, shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
+        let data = daemon::fetch::<Self>(self.daemon, &GitQuery::new(self.config)?)?;
+        Some(self.sections(data, shell, theme))
+    }
+}
+
+impl GitSegment<'_> {
+    fn sections(&self, data: GitData, shell: Shell, theme: &Theme) -> Vec<SegmentSection> {
+        let GitData {
+            branch,
+            origin,
+            worktree,
+            superproject,
+            sparse,
+            dirty_submodules,
+            state,
+            upstream,
+            ahead,
+            behind,
+            compared,
+            base,
+            stashes,
+            committed,
+            dirty_since,
+            staged,
+            unstaged,
+            untracked,
+            conflicted,
+            renamed,
+            deleted,
+            truncated,
+        } = data;
+
+        let mut sections = vec![];
+
+        if let Some(origin) = origin.filter(|_| self.config.forge || self.config.forge_label) {
+            let mut text = vec![];
+            if self.config.forge {
+                text.push(forge_icon(origin.forge(&self.config.forge_hosts)).to_owned());
+            }
+            if self.config.forge_label {
+                let label = format!("{}/{}", origin.owner, origin.name);
+                text.push(shell.escape(&label).into_owned());
+            }
             sections.push(SegmentSection::Section {
-                text: format!("{}✔", staged).into(),
-                bg: theme.git_staged_bg,
-                fg: theme.git_staged_fg,
+                text: text.join(" ").into(),
+                bg: theme.git_forge_bg,
+                fg: theme.git_forge_fg,
                 blinking: false,
             });
         }
 
-        if modified > 0 {
+        if let Some(superproject) = superproject {
             sections.push(SegmentSection::Section {
-                text: format!("{}✎", modified).into(),
-                bg: theme.git_modified_bg,
-                fg: theme.git_modified_fg,
+                text: format!("↳ {}", shell.escape(&superproject)).into(),
+                bg: theme.git_submodule_bg,
+                fg: theme.git_submodule_fg,
+                blinking: false,
+            });
+        }
+        if let Some(worktree) = worktree {
+            sections.push(SegmentSection::Section {
+                text: format!("⧉ {}", shell.escape(&worktree)).into(),
+                bg: theme.git_worktree_bg,
+                fg: theme.git_worktree_fg,
+                blinking: false,
+            });
+        }
+
+        sections.push(SegmentSection::Section {
+            text: format!(" {}", shell.escape(&branch)).into(),
+            bg: theme.git_branch_bg,
+            fg: theme.git_branch_fg,
+            blinking: false,
+        });
+
+        if sparse {
+            sections.push(SegmentSection::Section {
+                text: "sparse".into(),
+                bg: theme.git_sparse_bg,
+                fg: theme.git_sparse_fg,
                 blinking: false,
             });
         }
 
-        if untracked > 0 {
+        if let Some(state) = state {
             sections.push(SegmentSection::Section {
-                text: format!("{}+", untracked).into(),
-                bg: theme.git_untracked_bg,
-                fg: theme.git_untracked_fg,
+                text: shell.escape(&state).into_owned().into(),
+                bg: theme.git_state_bg,
+                fg: theme.git_state_fg,
                 blinking: false,
             });
         }
 
-        if conflicted > 0 {
+        match upstream {
+            Some(Upstream::NotConfigured) => sections.push(SegmentSection::Section {
+                text: "∅".into(),
+                bg: theme.git_upstream_bg,
+                fg: theme.git_upstream_fg,
+                blinking: false,
+            }),
+            Some(Upstream::Gone) => sections.push(SegmentSection::Section {
+                text: "⊘".into(),
+                bg: theme.git_upstream_bg,
+                fg: theme.git_upstream_fg,
+                blinking: false,
+            }),
+            Some(Upstream::Tracking(ref name)) => {
+                // remote names do not contain slashes
+                let differs = name.split_once('/').is_none_or(|(_, name)| name != branch);
+                if self.config.upstream_name && differs {
+                    sections.push(SegmentSection::Section {
+                        text: format!("→ {}", shell.escape(name)).into(),
+                        bg: theme.git_upstream_bg,
+                        fg: theme.git_upstream_fg,
+                        blinking: false,
+                    });
+                }
+                if compared && ahead == 0 && behind == 0 {
+                    sections.push(SegmentSection::Section {
+                        text: "≡".into(),
+                        bg: theme.git_upstream_bg,
+                        fg: theme.git_upstream_fg,
+                        blinking: false,
+                    });
+                }
+            }
+            None => {}
+        }
+
+        if ahead > 0 {
             sections.push(SegmentSection::Section {
-                text: format!("{}✼", conflicted).into(),
-                bg: theme.git_conflicted_bg,
-                fg: theme.git_conflicted_fg,
+                text: format!("{}⬆", ahead).into(),
+                bg: theme.git_ahead_bg,
+                fg: theme.git_ahead_fg,
                 blinking: false,
             });
         }
+        if behind > 0 {
+            sections.push(SegmentSection::Section {
+                text: format!("{}⬇", behind).into(),
+                bg: theme.git_behind_bg,
+                fg: theme.git_behind_fg,
+                blinking: false,
+            });
+        }
+
+        if let Some((base_branch, ahead, behind)) = base
+            && (ahead > 0 || behind > 0)
+        {
+            sections.push(SegmentSection::Section {
+                text: format!("{} +{}/-{}", shell.escape(&base_branch), ahead, behind).into(),
+                bg: theme.git_upstream_bg,
+                fg: theme.git_upstream_fg,
+                blinking: false,
+            });
+        }
+
+        if stashes > 0 {
+            sections.push(SegmentSection::Section {
+                text: format!("⚑{}", stashes).into(),
+                bg: theme.git_stash_bg,
+                fg: theme.git_stash_fg,
+                blinking: false,
+            });
+        }
+
+        // the commit age, in the warning colours once the changes are too old
+        let since = |time: SystemTime| SystemTime::now().duration_since(time).unwrap_or_default();
+        let dirty_warning = self
+            .config
+            .dirty_warning_hours
+            .zip(dirty_since)
+            .is_some_and(|(hours, dirty_since)| since(dirty_since).as_secs() >= hours * 3600);
+        if let Some(committed) = committed
+            && (self.config.commit_age || dirty_warning)
+        {
+            let (bg, fg) = if dirty_warning {
+                (theme.git_dirty_warning_bg, theme.git_dirty_warning_fg)
+            } else {
+                (theme.git_commit_age_bg, theme.git_commit_age_fg)
+            };
+            sections.push(SegmentSection::Section {
+                text: format_age(since(committed)).into(),
+                bg,
+                fg,
+                blinking: false,
+            });
+        }
+
+        if dirty_submodules > 0 {
+            sections.push(SegmentSection::Section {
+                text: format!("{}↳", dirty_submodules).into(),
+                bg: theme.git_submodule_bg,
+                fg: theme.git_submodule_fg,
+                blinking: false,
+            });
+        }
+
+        if truncated {
+            sections.push(SegmentSection::Section {
+                text: "?".into(),
+                bg: theme.git_modified_bg,
+                fg: theme.git_modified_fg,
+                blinking: false,
+            });
+            return sections;
+        }
+
+        let indicators = [
+            (
+                GitIndicator::Staged,
+                staged,
+                "✔",
+                theme.git_staged_bg,
+                theme.git_staged_fg,
+            ),
+            (
+                GitIndicator::Unstaged,
+                unstaged,
+                "✎",
+                theme.git_modified_bg,
+                theme.git_modified_fg,
+            ),
+            (
+                GitIndicator::Untracked,
+                untracked,
+                "+",
+                theme.git_untracked_bg,
+                theme.git_untracked_fg,
+            ),
+            (
+                GitIndicator::Conflicted,
+                conflicted,
+                "✼",
+                theme.git_conflicted_bg,
+                theme.git_conflicted_fg,
+            ),
+            (
+                GitIndicator::Renamed,
+                renamed,
+                "➜",
+                theme.git_renamed_bg,
+                theme.git_renamed_fg,
+            ),
+            (
+                GitIndicator::Deleted,
+                deleted,
+                "✖",
+                theme.git_deleted_bg,
+                theme.git_deleted_fg,
+            ),
+        ];
+        for (indicator, count, symbol, bg, fg) in indicators {
+            if count > 0 && self.config.indicators.contains(&indicator) {
+                sections.push(SegmentSection::Section {
+                    text: format!("{}{}", count, symbol).into(),
+                    bg,
+                    fg,
+                    blinking: false,
+                });
+            }
+        }
+
+        sections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_state() {
+        let dir = std::env::temp_dir().join(format!("opsline-git-state-{}", std::process::id()));
+        let repo = Repository::init(&dir).unwrap();
+        assert_eq!(operation_state(&repo), None);
+
+        let rebase = repo.path().join("rebase-merge");
+        std::fs::create_dir(&rebase).unwrap();
+        std::fs::write(rebase.join("interactive"), "").unwrap();
+        std::fs::write(rebase.join("msgnum"), "3\n").unwrap();
+        std::fs::write(rebase.join("end"), "7\n").unwrap();
+        std::fs::write(rebase.join("head-name"), "refs/heads/topic\n").unwrap();
+        assert_eq!(operation_state(&repo).as_deref(), Some("REBASE 3/7"));
+        assert_eq!(
+            rebase_file(&repo, "head-name").as_deref(),
+            Some("refs/heads/topic")
+        );
+
+        std::fs::remove_dir_all(&rebase).unwrap();
+        std::fs::write(repo.path().join("MERGE_HEAD"), "").unwrap();
+        assert_eq!(operation_state(&repo).as_deref(), Some("MERGING"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // a repository with one commit
+    fn init_repository(name: &str) -> (PathBuf, Repository, git2::Oid) {
+        let dir = std::env::temp_dir().join(format!("opsline-git-{}-{}", name, std::process::id()));
+        let repo = Repository::init(&dir).unwrap();
+        let signature = git2::Signature::now("opsline", "opsline@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let oid = repo
+            .commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[])
+            .unwrap();
+        drop(tree);
+
+        (dir, repo, oid)
+    }
+
+    fn query(cwd: &Path) -> GitQuery {
+        GitQuery {
+            cwd: cwd.to_owned(),
+            head: None,
+            index: None,
+            stash: None,
+            base_branch: None,
+            dirty_since: false,
+            status: StatusLimits {
+                untracked: true,
+                max_entries: None,
+                timeout_ms: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_detached_head_tag() {
+        let (dir, repo, oid) = init_repository("tag");
+        repo.tag_lightweight("v1.0.0", &repo.find_object(oid, None).unwrap(), false)
+            .unwrap();
+        repo.set_head_detached(oid).unwrap();
+
+        let data = GitSegment::collect(&query(&dir)).unwrap();
+        assert_eq!(data.branch, "v1.0.0");
+        assert_eq!(data.stashes, 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // texts of the sections displayed for data
+    fn texts(config: &GitConfiguration, data: GitData, shell: Shell) -> Vec<String> {
+        let theme = Theme::try_from("default").unwrap();
+        GitSegment::new(config, None)
+            .sections(data, shell, &theme)
+            .into_iter()
+            .filter_map(|section| match section {
+                SegmentSection::Section { text, .. } => Some(text.into_owned()),
+                SegmentSection::Seperator => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detached_head_hostile_tag() {
+        let (dir, repo, oid) = init_repository("hostile-tag");
+        repo.tag_lightweight("v$(id)`x`%F", &repo.find_object(oid, None).unwrap(), false)
+            .unwrap();
+        repo.set_head_detached(oid).unwrap();
+        let config = GitConfiguration::default();
+
+        let data = GitSegment::collect(&query(&dir)).unwrap();
+        assert_eq!(
+            texts(&config, data, Shell::Bash)[0],
+            "\u{e0a0} v\\\\$(id)\\\\`x\\\\`%F"
+        );
+        let data = GitSegment::collect(&query(&dir)).unwrap();
+        assert_eq!(
+            texts(&config, data, Shell::Zsh)[0],
+            "\u{e0a0} v\\$(id)\\`x\\`%%F"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_worktree_and_sparse_checkout() {
+        let (dir, repo, _) = init_repository("worktree");
+        let path = dir.with_file_name(format!("opsline-git-linked-{}", std::process::id()));
+        repo.worktree("linked", &path, None).unwrap();
+
+        let data = GitSegment::collect(&query(&dir)).unwrap();
+        assert_eq!(data.worktree, None);
+        assert!(!data.sparse);
+
+        repo.config()
+            .unwrap()
+            .set_bool("core.sparseCheckout", true)
+            .unwrap();
+        let data = GitSegment::collect(&query(&path)).unwrap();
+        assert_eq!(data.worktree.as_deref(), Some("linked"));
+        assert!(data.sparse);
+
+        std::fs::remove_dir_all(path).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_repository_in_modules_directory() {
+        let workspace =
+            std::env::temp_dir().join(format!("opsline-git-workspace-{}", std::process::id()));
+        let dir = workspace.join("modules").join("vpc");
+        let repo = Repository::init(&dir).unwrap();
+        let signature = git2::Signature::now("opsline", "opsline@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[])
+            .unwrap();
+
+        let data = GitSegment::collect(&query(&dir)).unwrap();
+        assert_eq!(data.superproject, None);
+
+        std::fs::remove_dir_all(workspace).unwrap();
+    }
+
+    #[test]
+    fn test_submodule() {
+        let (library, ..) = init_repository("submodule-library");
+        let (dir, ..) = init_repository("superproject");
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args([
+                    "-c",
+                    "protocol.file.allow=always",
+                    "-c",
+                    "user.name=opsline",
+                ])
+                .args(["-c", "user.email=opsline@example.com"])
+                .args(args)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["submodule", "add", &library.to_string_lossy(), "library"]);
+        git(&["commit", "-m", "library"]);
+
+        let submodule = dir.join("library");
+        let data = GitSegment::collect(&query(&submodule)).unwrap();
+        let name = dir.file_name().unwrap().to_string_lossy();
+        assert_eq!(data.superproject.as_deref(), Some(&*name));
+        assert_eq!(
+            GitSegment::collect(&query(&dir)).unwrap().superproject,
+            None
+        );
+
+        // through libgit2, then git status
+        let mut bounded = query(&dir);
+        bounded.status.max_entries = Some(100);
+        for query in [query(&dir), bounded] {
+            assert_eq!(GitSegment::collect(&query).unwrap().dirty_submodules, 0);
+        }
+        std::fs::write(submodule.join("notes.txt"), "").unwrap();
+        let mut bounded = query(&dir);
+        bounded.status.max_entries = Some(100);
+        for query in [query(&dir), bounded] {
+            assert_eq!(GitSegment::collect(&query).unwrap().dirty_submodules, 1);
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(library).unwrap();
+    }
+
+    #[test]
+    fn test_porcelain_status() {
+        let status = |record| porcelain_status(record).map(|entry| entry.status);
+        assert_eq!(
+            status(b"1 M. N... 100644 100644 100644 abc abc src/main.rs"),
+            Some(Status::INDEX_MODIFIED)
+        );
+        assert_eq!(
+            status(b"2 R. N... 100644 100644 100644 abc abc R100 new.rs"),
+            Some(Status::INDEX_RENAMED)
+        );
+        assert_eq!(
+            status(b"1 AM N... 000000 100644 100644 abc abc new.rs"),
+            Some(Status::INDEX_NEW | Status::WT_MODIFIED)
+        );
+        assert_eq!(
+            status(b"u UU N... 100644 100644 100644 100644 a b c f.rs"),
+            Some(Status::CONFLICTED)
+        );
+        assert_eq!(status(b"? notes.txt"), Some(Status::WT_NEW));
+        assert_eq!(status(b"! target/"), None);
+
+        let path = |record| porcelain_status(record).map(|entry| entry.path);
+        assert_eq!(
+            path(b"1 .M N... 100644 100644 100644 abc abc docs/release notes.md"),
+            Some("docs/release notes.md".into())
+        );
+        assert_eq!(
+            path(b"2 R. N... 100644 100644 100644 abc abc R100 new.rs"),
+            Some("new.rs".into())
+        );
+        assert_eq!(
+            path(b"u UU N... 100644 100644 100644 100644 a b c f.rs"),
+            Some("f.rs".into())
+        );
+        assert_eq!(path(b"? notes.txt"), Some("notes.txt".into()));
+    }
+
+    #[test]
+    fn test_git_status_max_entries() {
+        let dir = std::env::temp_dir().join(format!("opsline-git-status-{}", std::process::id()));
+        Repository::init(&dir).unwrap();
+        for name in ["a", "b", "c"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let limits = |max_entries| StatusLimits {
+            untracked: true,
+            max_entries,
+            timeout_ms: None,
+        };
+
+        let (statuses, complete) = git_status(&dir, &limits(Some(2))).unwrap();
+        assert_eq!((statuses.len(), complete), (2, false));
+
+        let (statuses, complete) = git_status(&dir, &limits(Some(3))).unwrap();
+        assert_eq!((statuses.len(), complete), (3, true));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_renamed() {
+        let (dir, repo, head) = init_repository("renamed");
+        std::fs::write(dir.join("old.rs"), "fn main() {}\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("old.rs")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("opsline", "opsline@example.com").unwrap();
+        let parent = repo.find_commit(head).unwrap();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "old",
+            &tree,
+            &[&parent],
+        )
+        .unwrap();
+
+        let moved = Command::new("git")
+            .arg("-C")
+            .arg(&dir)
+            .args(["mv", "old.rs", "new.rs"])
+            .status()
+            .unwrap();
+        assert!(moved.success());
+
+        let limits = StatusLimits {
+            untracked: true,
+            max_entries: None,
+            timeout_ms: None,
+        };
+        let (porcelain, _) = git_status(&dir, &limits).unwrap();
+        for statuses in [libgit2_status(&repo, &limits).unwrap(), porcelain] {
+            assert_eq!(statuses.len(), 1);
+            assert_eq!(statuses[0].path, Path::new("new.rs"));
+            let mut data = GitData::default();
+            classify(statuses[0].status, &mut data);
+            assert_eq!((data.staged, data.renamed), (1, 1));
+            assert_eq!((data.untracked, data.deleted), (0, 0));
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_classify() {
+        let mut data = GitData::default();
+        classify(Status::INDEX_MODIFIED | Status::WT_MODIFIED, &mut data);
+        classify(Status::INDEX_RENAMED, &mut data);
+        classify(Status::WT_DELETED, &mut data);
+        classify(Status::CONFLICTED | Status::INDEX_MODIFIED, &mut data);
+        classify(Status::WT_NEW, &mut data);
+
+        assert_eq!(data.staged, 2);
+        assert_eq!(data.unstaged, 2);
+        assert_eq!(data.renamed, 1);
+        assert_eq!(data.deleted, 1);
+        assert_eq!(data.conflicted, 1);
+        assert_eq!(data.untracked, 1);
+    }
+
+    #[test]
+    fn test_upstream() {
+        let (dir, repo, oid) = init_repository("upstream");
+        let branch = repo.head().unwrap().shorthand().unwrap().to_owned();
+
+        let data = GitSegment::collect(&query(&dir)).unwrap();
+        assert_eq!(data.upstream, Some(Upstream::NotConfigured));
+
+        let mut config = repo.config().unwrap();
+        config
+            .set_str(&format!("branch.{}.remote", branch), "origin")
+            .unwrap();
+        config
+            .set_str(&format!("branch.{}.merge", branch), "refs/heads/topic")
+            .unwrap();
+        let data = GitSegment::collect(&query(&dir)).unwrap();
+        assert_eq!(data.upstream, Some(Upstream::Gone));
+
+        repo.remote("origin", "https://example.com/opsline.git")
+            .unwrap();
+        repo.reference("refs/remotes/origin/topic", oid, false, "")
+            .unwrap();
+        let mut query = query(&dir);
+        query.base_branch = Some("origin/topic".into());
+        let data = GitSegment::collect(&query).unwrap();
+        assert_eq!(
+            data.upstream,
+            Some(Upstream::Tracking("origin/topic".into()))
+        );
+        assert_eq!((data.ahead, data.behind), (0, 0));
+        assert!(data.compared);
+        assert_eq!(data.base, Some(("origin/topic".into(), 0, 0)));
+
+        // an annotated tag as base, a hostile upstream name
+        let signature = git2::Signature::now("opsline", "opsline@example.com").unwrap();
+        repo.tag(
+            "v1",
+            &repo.find_object(oid, None).unwrap(),
+            &signature,
+            "",
+            false,
+        )
+        .unwrap();
+        repo.reference("refs/remotes/origin/$(id)", oid, false, "")
+            .unwrap();
+        repo.config()
+            .unwrap()
+            .set_str(&format!("branch.{}.merge", branch), "refs/heads/$(id)")
+            .unwrap();
+        let mut tag_query = query;
+        tag_query.base_branch = Some("v1".into());
+        let data = GitSegment::collect(&tag_query).unwrap();
+        assert_eq!(data.base, Some(("v1".into(), 0, 0)));
+        let config = GitConfiguration {
+            upstream_name: true,
+            ..Default::default()
+        };
+        assert!(texts(&config, data, Shell::Bash).contains(&r"→ origin/\\$(id)".to_owned()));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_remote_repository() {
+        let parse = |url| RemoteRepository::parse(url).map(|r| (r.host, r.owner, r.name));
+        let expected = |host: &str, owner: &str, name: &str| {
+            Some((host.to_owned(), owner.to_owned(), name.to_owned()))
+        };
+
+        assert_eq!(
+            parse("git@github.com:acme/opsline.git"),
+            expected("github.com", "acme", "opsline")
+        );
+        assert_eq!(
+            parse("https://gitlab.com/acme/platform/infra"),
+            expected("gitlab.com", "acme/platform", "infra")
+        );
+        assert_eq!(
+            parse("ssh://git@git.acme.corp:2222/ops/opsline.git"),
+            expected("git.acme.corp", "ops", "opsline")
+        );
+        assert_eq!(
+            parse("https://user@Bitbucket.org/acme/opsline.git/"),
+            expected("bitbucket.org", "acme", "opsline")
+        );
+        assert_eq!(parse("/srv/git/opsline.git"), None);
+
+        let hosts = BTreeMap::from([("git.acme.corp".to_owned(), Forge::Gitea)]);
+        let forge = |url| RemoteRepository::parse(url).unwrap().forge(&hosts);
+        assert_eq!(
+            forge("ssh://git@git.acme.corp/ops/opsline"),
+            Some(Forge::Gitea)
+        );
+        assert_eq!(forge("git@github.com:acme/opsline"), Some(Forge::Github));
+        assert_eq!(forge("git@example.com:acme/opsline"), None);
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(Duration::from_secs(42)), "now");
+        assert_eq!(format_age(Duration::from_secs(5 * 60 + 10)), "5m");
+        assert_eq!(format_age(Duration::from_secs(2 * 3600 + 59 * 60)), "2h");
+        assert_eq!(format_age(Duration::from_secs(3 * 86400 + 3600)), "3d");
+    }
+
+    #[test]
+    fn test_dirty_since() {
+        let (dir, repo, _) = init_repository("dirty");
+        let mut query = query(&dir);
+        query.dirty_since = true;
+
+        let data = GitSegment::collect(&query).unwrap();
+        assert!(data.committed.is_some());
+        assert_eq!(data.dirty_since, None);
+
+        // untracked files, however old, are not uncommitted work
+        let file = std::fs::File::create(dir.join("extracted.txt")).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(365 * 86400))
+            .unwrap();
+        let data = GitSegment::collect(&query).unwrap();
+        assert_eq!(data.dirty_since, None);
+
+        let old = SystemTime::now() - Duration::from_secs(5 * 3600);
+        let file = std::fs::File::create(dir.join("staged.txt")).unwrap();
+        file.set_modified(old).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("staged.txt")).unwrap();
+        index.write().unwrap();
+        let data = GitSegment::collect(&query).unwrap();
+        assert_eq!(data.dirty_since, Some(old));
 
-        Some(sections)
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,33 +1,126 @@
+use std::path::PathBuf;
+use std::time::SystemTime;
+
 use kube::config::Kubeconfig;
+use serde::{Deserialize, Serialize};
 
 use crate::Shell;
 use crate::configuration::KubeConfiguration;
+use crate::daemon::{self, Cacheable, DaemonClient};
 use crate::segments::{Segment, SegmentSection};
 use crate::theme::{ForegroundColor, Theme};
 
 pub struct KubeSegment<'a> {
     config: Option<&'a KubeConfiguration>,
+    daemon: Option<&'a DaemonClient>,
 }
 
 impl<'a> KubeSegment<'a> {
-    pub fn new(config: Option<&'a KubeConfiguration>) -> Self {
-        Self { config }
+    pub fn new(config: Option<&'a KubeConfiguration>, daemon: Option<&'a DaemonClient>) -> Self {
+        Self { config, daemon }
     }
 }
 
-impl Segment for KubeSegment<'_> {
-    fn name(&self) -> &'static str {
-        "kube"
+#[derive(Serialize, Deserialize)]
+pub struct KubeQuery {
+    // kubeconfig files with their modification time, so that the cache is
+    // invalidated as soon as the current context changes
+    paths: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl KubeQuery {
+    // same lookup as Kubeconfig::read()
+    fn new() -> Option<Self> {
+        let paths: Vec<_> = match std::env::var_os("KUBECONFIG") {
+            Some(value) => std::env::split_paths(&value)
+                .filter(|p| !p.as_os_str().is_empty())
+                .collect(),
+            None => Vec::new(),
+        };
+        let paths = if paths.is_empty() {
+            Vec::from([PathBuf::from(std::env::var_os("HOME")?).join(".kube/config")])
+        } else {
+            paths
+        };
+
+        Some(Self {
+            paths: paths
+                .into_iter()
+                .map(|path| {
+                    let modified = path.metadata().and_then(|m| m.modified()).ok();
+                    (path, modified)
+                })
+                .collect(),
+        })
     }
+}
+
+// kubectl and helm namespace overrides, then the namespace of the context
+fn resolve_namespace(env: impl Fn(&str) -> Option<String>, namespace: Option<String>) -> String {
+    ["KUBECTL_NAMESPACE", "HELM_NAMESPACE"]
+        .into_iter()
+        .filter_map(env)
+        .chain(namespace)
+        .find(|namespace| !namespace.is_empty())
+        .unwrap_or_else(|| "default".to_owned())
+}
 
-    fn output(&self, _shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
-        let config = Kubeconfig::read().ok()?;
+// critical contexts and namespaces are matched on a part of their name,
+// e.g. `prod` for `arn:aws:eks:eu-west-1:1234:cluster/prod`
+fn critical(name: &str, patterns: Option<&[String]>) -> bool {
+    patterns
+        .unwrap_or_default()
+        .iter()
+        .any(|pattern| name.contains(pattern.as_str()))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KubeData {
+    context: String,
+    namespace: Option<String>,
+}
+
+impl Cacheable for KubeSegment<'_> {
+    const NAME: &'static str = "kube";
+    type Query = KubeQuery;
+    type Data = KubeData;
+
+    fn collect(query: &KubeQuery) -> Option<KubeData> {
+        let config = query
+            .paths
+            .iter()
+            .try_fold(Kubeconfig::default(), |config, (path, _)| {
+                Kubeconfig::read_from(path).and_then(|c| config.merge(c))
+            })
+            .ok()?;
         let current_context = config.current_context?;
         let context = config
             .contexts
             .iter()
             .find(|c| c.name == current_context)
             .map(|c| c.context.as_ref())??;
+
+        Some(KubeData {
+            namespace: context.namespace.clone(),
+            context: current_context,
+        })
+    }
+}
+
+impl Segment for KubeSegment<'_> {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn priority(&self) -> u8 {
+        60
+    }
+
+    fn output(&self, shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
+        let KubeData {
+            context: current_context,
+            namespace,
+        } = daemon::fetch::<Self>(self.daemon, &KubeQuery::new()?)?;
         let mut sections = Vec::new();
 
         sections.push(SegmentSection::Section {
@@ -37,18 +130,18 @@ impl Segment for KubeSegment<'_> {
             blinking: false,
         });
 
-        if let Some(config) = self.config
-            && let Some(critical_contexts) = &config.critical_contexts
-            && critical_contexts
-                .iter()
-                .any(|c| current_context.contains(c))
-        {
-            sections.push(SegmentSection::Section {
-                text: "".into(),
-                bg: theme.kube_context_bg,
-                fg: ForegroundColor::from_color_code(196),
-                blinking: true,
-            })
+        let warning = || SegmentSection::Section {
+            text: "".into(),
+            bg: theme.kube_context_bg,
+            fg: ForegroundColor::from_color_code(196),
+            blinking: true,
+        };
+
+        if critical(
+            &current_context,
+            self.config.and_then(|c| c.critical_contexts.as_deref()),
+        ) {
+            sections.push(warning())
         }
 
         let alias = self.config.and_then(|c| {
@@ -59,25 +152,82 @@ impl Segment for KubeSegment<'_> {
                 .find(|ka| ka.context == current_context)
         });
         sections.push(SegmentSection::Section {
-            text: alias
-                .map(|a| a.alias.as_str())
-                .unwrap_or(current_context.as_str())
-                .to_string()
+            text: shell
+                .escape(alias.map_or(current_context.as_str(), |a| a.alias.as_str()))
+                .into_owned()
                 .into(),
             bg: theme.kube_context_bg,
             fg: theme.kube_context_fg,
             blinking: false,
         });
 
-        if let Some(ref namespace) = context.namespace {
-            sections.push(SegmentSection::Section {
-                text: namespace.to_string().into(),
-                bg: theme.kube_namespace_bg,
-                fg: theme.kube_namespace_fg,
-                blinking: false,
-            })
+        // the shell environment, not the daemon's
+        let namespace = resolve_namespace(|name| std::env::var(name).ok(), namespace);
+        if namespace == "default" && self.config.is_some_and(|c| c.hide_default_namespace) {
+            return Some(sections);
         }
 
+        if critical(
+            &namespace,
+            self.config.and_then(|c| c.critical_namespaces.as_deref()),
+        ) {
+            sections.push(warning())
+        }
+
+        sections.push(SegmentSection::Section {
+            text: shell.escape(&namespace).into_owned().into(),
+            bg: theme.kube_namespace_bg,
+            fg: theme.kube_namespace_fg,
+            blinking: false,
+        });
+
         Some(sections)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_namespace() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        assert_eq!(resolve_namespace(env(&[]), None), "default");
+        assert_eq!(resolve_namespace(env(&[]), Some("web".into())), "web");
+        assert_eq!(
+            resolve_namespace(env(&[("HELM_NAMESPACE", "ci")]), Some("web".into())),
+            "ci"
+        );
+        assert_eq!(
+            resolve_namespace(
+                env(&[("HELM_NAMESPACE", "ci"), ("KUBECTL_NAMESPACE", "ops")]),
+                None
+            ),
+            "ops"
+        );
+        assert_eq!(
+            resolve_namespace(env(&[("KUBECTL_NAMESPACE", "")]), Some("web".into())),
+            "web"
+        );
+    }
+
+    #[test]
+    fn test_critical() {
+        let patterns = ["prod".to_owned(), "kube-system".to_owned()];
+
+        assert!(critical(
+            "arn:aws:eks:eu-west-1:1234:cluster/prod",
+            Some(&patterns)
+        ));
+        assert!(critical("kube-system", Some(&patterns)));
+        assert!(!critical("staging", Some(&patterns)));
+        assert!(!critical("prod", None));
+    }
+}
@@ -7,6 +7,8 @@ pub mod aws;
 pub mod containers;
 pub mod cwd;
 pub mod devwrap;
+pub mod duration;
+pub mod exit;
 pub mod git;
 pub mod kube;
 pub mod readonly;
@@ -17,6 +19,8 @@ pub mod terraform;
 pub use containers::ContainersSegment;
 pub use cwd::CwdSegment;
 pub use devwrap::DevwrapSegment;
+pub use duration::DurationSegment;
+pub use exit::ExitSegment;
 pub use git::GitSegment;
 pub use kube::KubeSegment;
 pub use readonly::ReadonlySegment;
@@ -34,7 +38,14 @@ pub enum SegmentSection {
     Seperator,
 }
 
-pub trait Segment {
+pub trait Segment: Send + Sync {
     fn name(&self) -> &'static str;
+
+    /// Segments with the lowest priority are shortened, then dropped, first
+    /// when the prompt is wider than the terminal allows.
+    fn priority(&self) -> u8 {
+        50
+    }
+
     fn output(&self, shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>>;
 }
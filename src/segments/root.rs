@@ -15,10 +15,21 @@ impl Segment for RootSegment {
         "root"
     }
 
+    fn priority(&self) -> u8 {
+        100
+    }
+
     fn output(&self, shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
         let text = match shell {
             Shell::Bash => r"\$",
             Shell::Zsh => "%#",
+            Shell::Fish | Shell::Nu | Shell::Pwsh => {
+                if unsafe { libc::geteuid() } == 0 {
+                    "#"
+                } else {
+                    ">"
+                }
+            }
         };
 
         Some(Vec::from([SegmentSection::Section {
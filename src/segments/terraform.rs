@@ -18,6 +18,10 @@ impl Segment for TerraformSegment<'_> {
         "terraform"
     }
 
+    fn priority(&self) -> u8 {
+        60
+    }
+
     fn output(&self, _shell: Shell, theme: &Theme) -> Option<Vec<SegmentSection>> {
         let workspace = std::fs::read_to_string(".terraform/environment").ok()?;
         let mut sections = Vec::new();
@@ -1,7 +1,12 @@
+use std::borrow::Cow;
+
 #[derive(Clone, Copy)]
 pub enum Shell {
     Bash,
     Zsh,
+    Fish,
+    Nu,
+    Pwsh,
 }
 
 impl TryFrom<&str> for Shell {
@@ -11,7 +16,120 @@ impl TryFrom<&str> for Shell {
         match value {
             "bash" => Ok(Shell::Bash),
             "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "nu" => Ok(Shell::Nu),
+            "pwsh" => Ok(Shell::Pwsh),
             _ => Err("unknown shell".into()),
         }
     }
 }
+
+impl Shell {
+    /// Escapes `text` so that the shell displays it as is in the prompt,
+    /// e.g. a directory named `$(id)` in bash.
+    ///
+    /// Bash decodes its prompt escapes (`\\`, `\$`, ...) before expanding
+    /// variables and command substitutions in the result, so its text is
+    /// escaped for both passes. Zsh expands them before its `%` escapes when
+    /// `prompt_subst` is set, which the init script does.
+    pub fn escape<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Shell::Bash if text.contains(['\\', '$', '`']) => text
+                .replace('\\', r"\\\\")
+                .replace('$', r"\\$")
+                .replace('`', r"\\`")
+                .into(),
+            Shell::Zsh if text.contains(['\\', '$', '`', '%']) => text
+                .replace('\\', r"\\")
+                .replace('$', r"\$")
+                .replace('`', r"\`")
+                .replace('%', "%%")
+                .into(),
+            _ => text.into(),
+        }
+    }
+
+    /// The text displayed for `text` escaped by [`Shell::escape`].
+    pub fn unescape<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Shell::Bash if text.contains('\\') => {
+                let mut unescaped = String::with_capacity(text.len());
+                let mut rest = text;
+                while let Some(c) = rest.chars().next() {
+                    // the \$ of the root segment is displayed as $ too
+                    let (displayed, len) = if rest.starts_with(r"\\\\") {
+                        ('\\', 4)
+                    } else if rest.starts_with(r"\\$") {
+                        ('$', 3)
+                    } else if rest.starts_with(r"\\`") {
+                        ('`', 3)
+                    } else if rest.starts_with(r"\$") {
+                        ('$', 2)
+                    } else {
+                        (c, c.len_utf8())
+                    };
+                    unescaped.push(displayed);
+                    rest = &rest[len..];
+                }
+                unescaped.into()
+            }
+            Shell::Zsh if text.contains(['\\', '%']) => {
+                let mut unescaped = String::with_capacity(text.len());
+                let mut chars = text.chars().peekable();
+                while let Some(c) = chars.next() {
+                    match (c, chars.peek()) {
+                        ('\\', Some(&next @ ('\\' | '$' | '`'))) | ('%', Some(&next @ '%')) => {
+                            unescaped.push(next);
+                            chars.next();
+                        }
+                        _ => unescaped.push(c),
+                    }
+                }
+                unescaped.into()
+            }
+            _ => text.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(Shell::Bash.escape("~/src"), "~/src");
+        assert_eq!(Shell::Bash.escape(r"$(id)`id`\w"), r"\\$(id)\\`id\\`\\\\w");
+        assert_eq!(Shell::Bash.unescape(r"\$"), "$");
+        assert_eq!(Shell::Zsh.escape("100%"), "100%%");
+        assert_eq!(Shell::Zsh.escape(r"$(id)`id`\w"), r"\$(id)\`id\`\\w");
+        assert_eq!(Shell::Fish.escape("$(id)"), "$(id)");
+
+        for text in [r"$(id)`id`\w", r"\$", "100%", "%%"] {
+            for shell in [Shell::Bash, Shell::Zsh] {
+                assert_eq!(shell.unescape(&shell.escape(text)), text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_escape_bash_prompt() {
+        let text = r"~/$(echo x)/`echo y`/\w/$HOME/\";
+
+        // an interactive bash prints its prompt on stderr, then exit on
+        // end of input
+        let output = std::process::Command::new("bash")
+            .args(["--norc", "--noprofile", "-i"])
+            .env_clear()
+            .env("PS1", Shell::Bash.escape(text).as_ref())
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert_eq!(
+            stderr.lines().last(),
+            Some(format!("{}exit", text).as_str())
+        );
+    }
+}
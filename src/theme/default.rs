@@ -5,14 +5,24 @@ pub const DEFAULT: Theme = Theme {
     aws_fg: ForegroundColor::from_color_code(214),
     cwd_bg: BackgroundColor::colorless(),
     cwd_fg: ForegroundColor::from_color_code(250),
+    cwd_path_bg: BackgroundColor::colorless(),
+    cwd_path_fg: ForegroundColor::from_color_code(245),
+    cwd_alias_bg: BackgroundColor::colorless(),
+    cwd_alias_fg: ForegroundColor::from_color_code(33),
     container_bg: BackgroundColor::colorless(),
     container_fg: ForegroundColor::from_color_code(177),
+    duration_bg: BackgroundColor::colorless(),
+    duration_fg: ForegroundColor::from_color_code(220),
+    exit_bg: BackgroundColor::colorless(),
+    exit_fg: ForegroundColor::from_color_code(196),
     git_branch_bg: BackgroundColor::colorless(),
     git_branch_fg: ForegroundColor::from_color_code(15),
     git_ahead_bg: BackgroundColor::colorless(),
     git_ahead_fg: ForegroundColor::from_color_code(250),
     git_behind_bg: BackgroundColor::colorless(),
     git_behind_fg: ForegroundColor::from_color_code(250),
+    git_upstream_bg: BackgroundColor::colorless(),
+    git_upstream_fg: ForegroundColor::from_color_code(250),
     git_modified_bg: BackgroundColor::colorless(),
     git_modified_fg: ForegroundColor::from_color_code(14),
     git_staged_bg: BackgroundColor::colorless(),
@@ -21,6 +31,26 @@ pub const DEFAULT: Theme = Theme {
     git_untracked_fg: ForegroundColor::from_color_code(15),
     git_conflicted_bg: BackgroundColor::colorless(),
     git_conflicted_fg: ForegroundColor::from_color_code(15),
+    git_renamed_bg: BackgroundColor::colorless(),
+    git_renamed_fg: ForegroundColor::from_color_code(15),
+    git_deleted_bg: BackgroundColor::colorless(),
+    git_deleted_fg: ForegroundColor::from_color_code(9),
+    git_worktree_bg: BackgroundColor::colorless(),
+    git_worktree_fg: ForegroundColor::from_color_code(250),
+    git_submodule_bg: BackgroundColor::colorless(),
+    git_submodule_fg: ForegroundColor::from_color_code(250),
+    git_sparse_bg: BackgroundColor::colorless(),
+    git_sparse_fg: ForegroundColor::from_color_code(250),
+    git_forge_bg: BackgroundColor::colorless(),
+    git_forge_fg: ForegroundColor::from_color_code(250),
+    git_state_bg: BackgroundColor::colorless(),
+    git_state_fg: ForegroundColor::from_color_code(11),
+    git_stash_bg: BackgroundColor::colorless(),
+    git_stash_fg: ForegroundColor::from_color_code(250),
+    git_commit_age_bg: BackgroundColor::colorless(),
+    git_commit_age_fg: ForegroundColor::from_color_code(245),
+    git_dirty_warning_bg: BackgroundColor::colorless(),
+    git_dirty_warning_fg: ForegroundColor::from_color_code(208),
     kube_context_bg: BackgroundColor::colorless(),
     kube_context_fg: ForegroundColor::from_color_code(117),
     kube_namespace_bg: BackgroundColor::colorless(),
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, de};
+
+use super::{Theme, ThemeColor};
+use crate::configuration;
+
+const EXTENSIONS: [&str; 3] = ["toml", "yaml", "yml"];
+
+// every Theme field, or only some of them when extending a compiled-in theme
+struct ThemeFile {
+    extends: Option<String>,
+    colors: BTreeMap<String, ThemeColor>,
+}
+
+// hand written rather than #[serde(flatten)] which loses the location of
+// invalid colours
+impl<'de> Deserialize<'de> for ThemeFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = ThemeFile;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of theme colours")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<ThemeFile, A::Error> {
+                let mut file = ThemeFile {
+                    extends: None,
+                    colors: BTreeMap::new(),
+                };
+
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == "extends" {
+                        file.extends = Some(map.next_value()?);
+                    } else {
+                        let color = map.next_value()?;
+                        file.colors.insert(key, color);
+                    }
+                }
+
+                Ok(file)
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+/// Looks for `<name>.toml`, `<name>.yaml` or `<name>.yml` in the themes
+/// configuration directory.
+pub fn find(name: &str) -> Option<PathBuf> {
+    let dir = configuration::config_dir()?.join("themes");
+
+    EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(name).with_extension(extension))
+        .find(|path| path.exists())
+}
+
+pub fn from_file(path: &Path) -> Result<Theme, Box<dyn std::error::Error>> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let theme = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => serde_saphyr::from_str(&content).map_err(|e| e.to_string()),
+        _ => toml::from_str(&content).map_err(|e| e.to_string()),
+    }
+    .and_then(from_theme_file);
+
+    theme.map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+fn from_theme_file(file: ThemeFile) -> Result<Theme, String> {
+    let mut theme = match file.extends {
+        Some(ref name) => {
+            let base = Theme::try_from(name.as_str()).map_err(|e| format!("{}: {}", e, name))?;
+            match serde_json::to_value(base).map_err(|e| e.to_string())? {
+                serde_json::Value::Object(theme) => theme,
+                _ => unreachable!("themes serialize to maps"),
+            }
+        }
+        None => serde_json::Map::new(),
+    };
+
+    for (key, color) in file.colors {
+        theme.insert(key, serde_json::to_value(color).map_err(|e| e.to_string())?);
+    }
+
+    serde_json::from_value(theme.into()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::Color;
+
+    #[test]
+    fn test_extends() {
+        let file: ThemeFile = toml::from_str(
+            r#"
+extends = "gruvbox"
+cwd_fg = 33
+cwd_bg = 236
+"#,
+        )
+        .unwrap();
+        let theme = from_theme_file(file).unwrap();
+
+        assert_eq!(theme.cwd_fg.0, Some(Color::Indexed(33)));
+        assert_eq!(theme.cwd_bg.0, Some(Color::Indexed(236)));
+        assert_eq!(theme.root_fg.0, Some(Color::Indexed(223)));
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let file: ThemeFile = toml::from_str("cwd_fg = 33").unwrap();
+        let err = from_theme_file(file).err().unwrap();
+        assert!(err.contains("missing field"), "{}", err);
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        let file: ThemeFile = toml::from_str("extends = \"default\"\ncwd_fgg = 33").unwrap();
+        let err = from_theme_file(file).err().unwrap();
+        assert!(err.contains("unknown field `cwd_fgg`"), "{}", err);
+    }
+
+    #[test]
+    fn test_invalid_color_code() {
+        let err = toml::from_str::<ThemeFile>("cwd_fg = 256").err().unwrap();
+        assert!(err.to_string().contains("256-colour code"), "{}", err);
+
+        let file: ThemeFile = serde_saphyr::from_str("extends: default\nroot_bg: none").unwrap();
+        assert_eq!(from_theme_file(file).unwrap().root_bg.0, None);
+    }
+
+    #[test]
+    fn test_hex_color() {
+        let file: ThemeFile =
+            toml::from_str("extends = \"default\"\ncwd_bg = \"#d79921\"").unwrap();
+        let theme = from_theme_file(file).unwrap();
+        assert_eq!(theme.cwd_bg.0, Some(Color::Rgb(0xd7, 0x99, 0x21)));
+
+        let err = toml::from_str::<ThemeFile>("cwd_fg = \"#d7992\"")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("#rrggbb"), "{}", err);
+    }
+}
@@ -1,14 +1,156 @@
 use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
 
 use crate::shell::Shell;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Color {
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+// levels of the 6x6x6 colour cube of the 256-colour palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl Color {
+    // nearest entry of the colour cube (16-231) or of the grayscale ramp (232-255)
+    fn to_indexed(self) -> u8 {
+        let (r, g, b) = match self {
+            Color::Indexed(code) => return code,
+            Color::Rgb(r, g, b) => (r, g, b),
+        };
+
+        fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+            let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).unsigned_abs().pow(2);
+            d(r1, r2) + d(g1, g2) + d(b1, b2)
+        }
+
+        let nearest_level = |v: u8| {
+            (0..CUBE_LEVELS.len())
+                .min_by_key(|&i| CUBE_LEVELS[i].abs_diff(v))
+                .unwrap()
+        };
+        let (ri, gi, bi) = (nearest_level(r), nearest_level(g), nearest_level(b));
+        let cube = (CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+
+        let average = (u32::from(r) + u32::from(g) + u32::from(b)) / 3;
+        let gray_index = (average.saturating_sub(3) / 10).min(23) as u8;
+        let gray_level = 8 + 10 * gray_index;
+        let gray = (gray_level, gray_level, gray_level);
+
+        if distance((r, g, b), gray) < distance((r, g, b), cube) {
+            232 + gray_index
+        } else {
+            16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+        }
+    }
+
+    // SGR parameters following 38 or 48
+    fn fmt_sgr(self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Color::Rgb(r, g, b) if truecolor() => write!(f, "2;{};{};{}", r, g, b),
+            color => write!(f, "5;{}", color.to_indexed()),
+        }
+    }
+}
+
+fn truecolor() -> bool {
+    static TRUECOLOR: OnceLock<bool> = OnceLock::new();
+
+    *TRUECOLOR
+        .get_or_init(|| std::env::var("COLORTERM").is_ok_and(|v| v == "truecolor" || v == "24bit"))
+}
+
+// colour as written in theme files: a 256-colour code, a #rrggbb hex code, or "none"
 #[derive(Clone, Copy)]
-pub struct BackgroundColor(Option<u8>);
+struct ThemeColor(Option<Color>);
+
+impl Serialize for ThemeColor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Some(Color::Indexed(code)) => serializer.serialize_u8(code),
+            Some(Color::Rgb(r, g, b)) => {
+                serializer.serialize_str(&format!("#{:02x}{:02x}{:02x}", r, g, b))
+            }
+            None => serializer.serialize_str("none"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ThemeColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = ThemeColor;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    f,
+                    "a 256-colour code between 0 and 255, a \"#rrggbb\" colour, or \"none\""
+                )
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<ThemeColor, E> {
+                u8::try_from(v)
+                    .map(|code| ThemeColor(Some(Color::Indexed(code))))
+                    .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<ThemeColor, E> {
+                u8::try_from(v)
+                    .map(|code| ThemeColor(Some(Color::Indexed(code))))
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<ThemeColor, E> {
+                if v == "none" {
+                    return Ok(ThemeColor(None));
+                }
+
+                v.strip_prefix('#')
+                    .filter(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .map(|rgb| {
+                        let [_, r, g, b] = rgb.to_be_bytes();
+                        ThemeColor(Some(Color::Rgb(r, g, b)))
+                    })
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(from = "ThemeColor", into = "ThemeColor")]
+pub struct BackgroundColor(Option<Color>);
+
+impl From<ThemeColor> for BackgroundColor {
+    fn from(color: ThemeColor) -> Self {
+        Self(color.0)
+    }
+}
+
+impl From<BackgroundColor> for ThemeColor {
+    fn from(bg: BackgroundColor) -> Self {
+        Self(bg.0)
+    }
+}
 
 impl BackgroundColor {
     #[allow(dead_code)]
     pub const fn from_color_code(color_code: u8) -> Self {
-        Self(Some(color_code))
+        Self(Some(Color::Indexed(color_code)))
+    }
+
+    #[allow(dead_code)]
+    pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        Self(Some(Color::Rgb(r, g, b)))
     }
 
     pub const fn colorless() -> Self {
@@ -19,9 +161,10 @@ impl BackgroundColor {
 impl fmt::Display for BackgroundColor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(bg) = self.0 {
-            write!(f, "{}", bg)
+            write!(f, "48;")?;
+            bg.fmt_sgr(f)
         } else {
-            write!(f, "")
+            write!(f, "49")
         }
     }
 }
@@ -33,8 +176,9 @@ impl BackgroundColor {
         impl fmt::Display for Helper {
             fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
                 match self.1 {
-                    Shell::Bash => write!(f, r"\[\e[48;5;{}m\]", self.0),
-                    Shell::Zsh => write!(f, "%{{\x1b[48;5;{}m%}}", self.0),
+                    Shell::Bash => write!(f, r"\[\e[{}m\]", self.0),
+                    Shell::Zsh => write!(f, "%{{\x1b[{}m%}}", self.0),
+                    Shell::Fish | Shell::Nu | Shell::Pwsh => write!(f, "\x1b[{}m", self.0),
                 }
             }
         }
@@ -43,12 +187,30 @@ impl BackgroundColor {
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct ForegroundColor(Option<u8>);
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(from = "ThemeColor", into = "ThemeColor")]
+pub struct ForegroundColor(Option<Color>);
+
+impl From<ThemeColor> for ForegroundColor {
+    fn from(color: ThemeColor) -> Self {
+        Self(color.0)
+    }
+}
+
+impl From<ForegroundColor> for ThemeColor {
+    fn from(fg: ForegroundColor) -> Self {
+        Self(fg.0)
+    }
+}
 
 impl ForegroundColor {
     pub const fn from_color_code(color_code: u8) -> Self {
-        Self(Some(color_code))
+        Self(Some(Color::Indexed(color_code)))
+    }
+
+    #[allow(dead_code)]
+    pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        Self(Some(Color::Rgb(r, g, b)))
     }
 
     #[allow(dead_code)]
@@ -66,9 +228,10 @@ impl From<BackgroundColor> for ForegroundColor {
 impl fmt::Display for ForegroundColor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(fg) = self.0 {
-            write!(f, "{}", fg)
+            write!(f, "38;")?;
+            fg.fmt_sgr(f)
         } else {
-            write!(f, "")
+            write!(f, "39")
         }
     }
 }
@@ -79,8 +242,9 @@ impl ForegroundColor {
         impl fmt::Display for Helper {
             fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
                 match self.1 {
-                    Shell::Bash => write!(f, r"\[\e[38;5;{}m\]", self.0),
-                    Shell::Zsh => write!(f, "%{{\x1b[38;5;{}m%}}", self.0),
+                    Shell::Bash => write!(f, r"\[\e[{}m\]", self.0),
+                    Shell::Zsh => write!(f, "%{{\x1b[{}m%}}", self.0),
+                    Shell::Fish | Shell::Nu | Shell::Pwsh => write!(f, "\x1b[{}m", self.0),
                 }
             }
         }
@@ -100,6 +264,7 @@ impl Blink {
                 match self.1 {
                     Shell::Bash => write!(f, r"\[\e[5m\]"),
                     Shell::Zsh => write!(f, "%{{\x1b[5m%}}"),
+                    Shell::Fish | Shell::Nu | Shell::Pwsh => write!(f, "\x1b[5m"),
                 }
             }
         }
@@ -119,6 +284,7 @@ impl Reset {
                 match self.1 {
                     Shell::Bash => write!(f, r"\[\e[0m\]"),
                     Shell::Zsh => write!(f, "%{{\x1b[0m%}}"),
+                    Shell::Fish | Shell::Nu | Shell::Pwsh => write!(f, "\x1b[0m"),
                 }
             }
         }
@@ -127,6 +293,8 @@ impl Reset {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Theme {
     pub aws_bg: BackgroundColor,
     pub aws_fg: ForegroundColor,
@@ -134,12 +302,22 @@ pub struct Theme {
     pub container_fg: ForegroundColor,
     pub cwd_bg: BackgroundColor,
     pub cwd_fg: ForegroundColor,
+    pub cwd_path_bg: BackgroundColor,
+    pub cwd_path_fg: ForegroundColor,
+    pub cwd_alias_bg: BackgroundColor,
+    pub cwd_alias_fg: ForegroundColor,
+    pub duration_bg: BackgroundColor,
+    pub duration_fg: ForegroundColor,
+    pub exit_bg: BackgroundColor,
+    pub exit_fg: ForegroundColor,
     pub git_branch_bg: BackgroundColor,
     pub git_branch_fg: ForegroundColor,
     pub git_ahead_bg: BackgroundColor,
     pub git_ahead_fg: ForegroundColor,
     pub git_behind_bg: BackgroundColor,
     pub git_behind_fg: ForegroundColor,
+    pub git_upstream_bg: BackgroundColor,
+    pub git_upstream_fg: ForegroundColor,
     pub git_modified_bg: BackgroundColor,
     pub git_modified_fg: ForegroundColor,
     pub git_staged_bg: BackgroundColor,
@@ -148,6 +326,26 @@ pub struct Theme {
     pub git_untracked_fg: ForegroundColor,
     pub git_conflicted_bg: BackgroundColor,
     pub git_conflicted_fg: ForegroundColor,
+    pub git_renamed_bg: BackgroundColor,
+    pub git_renamed_fg: ForegroundColor,
+    pub git_deleted_bg: BackgroundColor,
+    pub git_deleted_fg: ForegroundColor,
+    pub git_worktree_bg: BackgroundColor,
+    pub git_worktree_fg: ForegroundColor,
+    pub git_submodule_bg: BackgroundColor,
+    pub git_submodule_fg: ForegroundColor,
+    pub git_sparse_bg: BackgroundColor,
+    pub git_sparse_fg: ForegroundColor,
+    pub git_forge_bg: BackgroundColor,
+    pub git_forge_fg: ForegroundColor,
+    pub git_state_bg: BackgroundColor,
+    pub git_state_fg: ForegroundColor,
+    pub git_stash_bg: BackgroundColor,
+    pub git_stash_fg: ForegroundColor,
+    pub git_commit_age_bg: BackgroundColor,
+    pub git_commit_age_fg: ForegroundColor,
+    pub git_dirty_warning_bg: BackgroundColor,
+    pub git_dirty_warning_fg: ForegroundColor,
     pub kube_context_bg: BackgroundColor,
     pub kube_context_fg: ForegroundColor,
     pub kube_namespace_bg: BackgroundColor,
@@ -165,6 +363,7 @@ pub struct Theme {
 }
 
 mod default;
+mod file;
 mod gruvbox;
 
 impl TryFrom<&str> for Theme {
@@ -178,3 +377,37 @@ impl TryFrom<&str> for Theme {
         }
     }
 }
+
+impl Theme {
+    /// Loads a compiled-in theme, a theme file, or a theme file found in
+    /// the `themes` configuration directory.
+    pub fn load(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Ok(theme) = Theme::try_from(name) {
+            return Ok(theme);
+        }
+
+        let path = Path::new(name);
+        if path.components().count() > 1 || path.extension().is_some() {
+            return file::from_file(path);
+        }
+
+        match file::find(name) {
+            Some(path) => file::from_file(&path),
+            None => Err(format!("unknown theme: {}", name).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_indexed_color() {
+        assert_eq!(Color::Indexed(42).to_indexed(), 42);
+        assert_eq!(Color::Rgb(0, 0, 0).to_indexed(), 16);
+        assert_eq!(Color::Rgb(255, 255, 255).to_indexed(), 231);
+        assert_eq!(Color::Rgb(0xd7, 0x99, 0x21).to_indexed(), 172);
+        assert_eq!(Color::Rgb(0x28, 0x28, 0x28).to_indexed(), 235);
+    }
+}